@@ -0,0 +1,128 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use regex::Regex;
+
+use crate::{config::Config, links::normalize, markdown::MdInfo, permalink::output_path};
+
+/// Stands in for `{BACKLINKS}` until every post is rendered and the link graph is known
+pub const MARKER: &str = "<!--ssg:backlinks-->";
+
+/// Output files inside `build_dir` that the post body `html`, written to `dest`, links to
+///
+/// External links and links to the post itself are skipped.
+pub fn internal_links(html: &str, dest: &Path, build_dir: &Path, c: &Config) -> Vec<PathBuf> {
+    let link_re = Regex::new(r#"\bhref=(?:"([^"]*)"|'([^']*)')"#).unwrap();
+    let scheme = Regex::new(r"^[[:alpha:]][[:alnum:]+.-]*:").unwrap();
+    let site_url = c.site_url();
+    let base_path = c.base_path();
+    let dest = normalize(dest);
+
+    let mut res = vec![];
+    for caps in link_re.captures_iter(html) {
+        let url = caps.get(1).or_else(|| caps.get(2)).unwrap().as_str();
+        let url = url.strip_prefix(&site_url).unwrap_or(url);
+        if url.starts_with("//") || scheme.is_match(url) {
+            continue;
+        }
+        let path = url.split(['#', '?']).next().unwrap();
+        if path.is_empty() {
+            continue;
+        }
+        let target = match path.strip_prefix('/') {
+            Some(rooted) => {
+                let rooted = rooted
+                    .strip_prefix(base_path.trim_start_matches('/'))
+                    .filter(|x| !base_path.is_empty() && (x.is_empty() || x.starts_with('/')))
+                    .unwrap_or(rooted);
+                build_dir.join(output_path(rooted.trim_start_matches('/')))
+            }
+            None => dest.parent().unwrap().join(output_path(path)),
+        };
+        let target = normalize(&target);
+        if target != dest && !res.contains(&target) {
+            res.push(target);
+        }
+    }
+    res
+}
+
+/// Posts linking to each output file, oldest first
+///
+/// `links` pairs every post with the output files its body links to.
+pub fn link_graph<'a>(
+    links: impl IntoIterator<Item = (&'a MdInfo, &'a [PathBuf])>,
+) -> HashMap<PathBuf, Vec<&'a MdInfo>> {
+    let mut res: HashMap<PathBuf, Vec<&MdInfo>> = HashMap::new();
+    for (post, targets) in links {
+        for target in targets {
+            res.entry(target.clone()).or_default().push(post);
+        }
+    }
+    for sources in res.values_mut() {
+        sources.sort();
+        sources.dedup_by(|a, b| a.path == b.path);
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn links(html: &str, base_path: &str) -> Vec<PathBuf> {
+        let c = Config {
+            hosted_url: "https://example.com/".to_owned(),
+            base_path: base_path.to_owned(),
+            ..Default::default()
+        };
+        let dest = Path::new("static/blog/a/index.html");
+        internal_links(html, dest, Path::new("static"), &c)
+    }
+
+    fn paths(paths: &[&str]) -> Vec<PathBuf> {
+        paths.iter().map(PathBuf::from).collect()
+    }
+
+    #[test]
+    fn relative_links() {
+        let html = r#"<a href="../b/">b</a> <a href='../c.html#x'>c</a> <a href="../b/?q=1">b</a>"#;
+        assert_eq!(
+            links(html, ""),
+            paths(&["static/blog/b/index.html", "static/blog/c.html"])
+        );
+    }
+
+    #[test]
+    fn root_relative_links_drop_the_base_path() {
+        let html = r#"<a href="/project/blog/b/">b</a> <a href="/blog/c/">c</a> <a href="/projectx/d.html">d</a>"#;
+        assert_eq!(
+            links(html, "project"),
+            paths(&[
+                "static/blog/b/index.html",
+                "static/blog/c/index.html",
+                "static/projectx/d.html"
+            ])
+        );
+    }
+
+    #[test]
+    fn absolute_links_count_only_within_the_site() {
+        let html = r#"<a href="https://example.com/project/blog/b/">b</a>
+            <a href="https://other.org/blog/c/">c</a> <a href="//cdn.example.com/x.js">x</a>
+            <a href="mailto:me@example.com">me</a>"#;
+        assert_eq!(
+            links(html, "/project/"),
+            paths(&["static/blog/b/index.html"])
+        );
+    }
+
+    #[test]
+    fn self_links_are_skipped() {
+        let html = r##"<a href="#top">top</a> <a href="./">a</a> <a href="/blog/a/">a</a>
+            <a href="https://example.com/blog/a/#x">a</a>"##;
+        assert!(links(html, "").is_empty());
+    }
+}
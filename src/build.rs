@@ -0,0 +1,949 @@
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt,
+    fs::{
+        copy, create_dir, create_dir_all, read, read_dir, read_to_string, remove_dir,
+        remove_dir_all, remove_file, rename, write,
+    },
+    io,
+    path::{Path, PathBuf},
+    sync::{
+        Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    thread,
+};
+
+use chrono::{DateTime, Utc};
+use glob::Pattern;
+use pathdiff::diff_paths;
+use rss_gen::{RssData, RssError, RssItem, RssVersion, generate_rss};
+
+use crate::{
+    backlinks::{MARKER, internal_links, link_graph},
+    cache::RenderCache,
+    collection::{self, Collection, load_collections},
+    config::{Config, glob_matches},
+    highlight::{add_stylesheet_link, highlight_html, rewrite_fences, stylesheet},
+    html::{HtmlError, add_base_path, generate_substituted_html, render_items, render_layout},
+    ignore::Ignore,
+    links::{LinkContext, normalize, rewrite_links},
+    markdown::{
+        MdError, MdInfo, RenderOptions, add_meta_to_post_html, get_md_info, math_head,
+        render_to_html, truncate_content,
+    },
+    permalink,
+    redirect::{RedirectError, collect_redirects, write_redirects},
+    related::related_posts,
+    rss::add_rss_meta,
+    series::{self, Series, collect_series, series_index, series_nav},
+    toc::{anchor_headings, insert_toc, toc_html},
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum BuildError {
+    #[error(transparent)]
+    Md(#[from] MdError),
+    #[error("{source}")]
+    Html {
+        path: PathBuf,
+        #[source]
+        source: HtmlError,
+    },
+    #[error(transparent)]
+    Redirect(#[from] RedirectError),
+    #[error("I/O on {path}: {source}")]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Generating feed {path}: {source}")]
+    Feed {
+        path: PathBuf,
+        #[source]
+        source: RssError,
+    },
+    #[error(
+        "{path} holds files matching `keep` that an earlier build failed to carry over; move them back into the output dir or delete {path}"
+    )]
+    UnrecoveredKeep { path: PathBuf },
+    #[error(
+        "{path} is part of series '{series}', but no `series_layout` is set for its index page"
+    )]
+    NoSeriesLayout { path: PathBuf, series: String },
+}
+
+impl BuildError {
+    pub fn path(&self) -> &Path {
+        match self {
+            BuildError::Md(e) => e.path(),
+            BuildError::Redirect(e) => e.path(),
+            BuildError::Html { path, .. }
+            | BuildError::Io { path, .. }
+            | BuildError::Feed { path, .. }
+            | BuildError::UnrecoveredKeep { path }
+            | BuildError::NoSeriesLayout { path, .. } => path,
+        }
+    }
+}
+
+/// Every error of a failed build
+#[derive(Debug)]
+pub struct BuildErrors(pub Vec<BuildError>);
+
+impl std::error::Error for BuildErrors {}
+
+impl fmt::Display for BuildErrors {
+    /// Errors grouped by the file they concern
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut by_path: BTreeMap<&Path, Vec<&BuildError>> = BTreeMap::new();
+        for e in &self.0 {
+            by_path.entry(e.path()).or_default().push(e);
+        }
+        write!(f, "Build failed with {} error(s)", self.0.len())?;
+        for (path, errors) in by_path {
+            write!(f, "\n\n{}", path.display())?;
+            for e in errors {
+                for (i, line) in e.to_string().lines().enumerate() {
+                    let bullet = if i == 0 { "  - " } else { "    " };
+                    write!(f, "\n{bullet}{line}")?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl From<BuildError> for BuildErrors {
+    fn from(e: BuildError) -> Self {
+        BuildErrors(vec![e])
+    }
+}
+
+fn io_error(path: &Path) -> impl FnOnce(io::Error) -> BuildError {
+    let path = path.to_path_buf();
+    move |source| BuildError::Io { path, source }
+}
+
+/// Paths and loaded content shared by every file of a build
+struct Site<'a> {
+    c: &'a Config,
+    site_dir: &'a Path,
+    src_dir: PathBuf,
+    build_dir: &'a Path,
+    components_dir: PathBuf,
+    static_dir: Option<PathBuf>,
+    styles_css: PathBuf,
+    collections: Vec<Collection>,
+    series: Vec<Series>,
+    /// `{RELATED}` posts of every post, when configured
+    related: HashMap<PathBuf, Vec<MdInfo>>,
+    ignore: Ignore,
+    cache: Option<RenderCache>,
+}
+
+/// Counts reported after a successful build
+pub struct BuildSummary {
+    pub pages: usize,
+    pub posts: usize,
+    pub cache_hits: usize,
+    pub cache_misses: usize,
+}
+
+impl fmt::Display for BuildSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Built {} page(s) and {} post(s); render cache: {} hit(s), {} miss(es)",
+            self.pages, self.posts, self.cache_hits, self.cache_misses
+        )
+    }
+}
+
+/// What rendering a source file produced, written out once every file is rendered
+enum Output<'a> {
+    Write {
+        dest: PathBuf,
+        contents: String,
+        /// Collection name and feed item of a post
+        feed_item: Option<(&'a str, Box<RssItem>)>,
+        /// A post and the output files its body links to
+        links: Option<(Box<Cow<'a, MdInfo>>, Vec<PathBuf>)>,
+    },
+    Copy {
+        src: PathBuf,
+        dest: PathBuf,
+    },
+    /// A post that failed to load, already reported by `load_collections`
+    Skip,
+}
+
+/// Number of render threads when `--jobs` isn't given
+pub fn default_jobs() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+pub struct BuildOptions {
+    /// Posts rendered in parallel
+    pub jobs: usize,
+    /// Reuse and store pandoc output in the render cache
+    pub cache: bool,
+}
+
+impl Default for BuildOptions {
+    fn default() -> Self {
+        BuildOptions {
+            jobs: default_jobs(),
+            cache: true,
+        }
+    }
+}
+
+/// Builds into a sibling of `build_dir` and swaps it into place only if the build succeeded,
+/// so a failed build leaves the previous output untouched
+pub fn build(
+    site_dir: &Path,
+    build_dir: &Path,
+    c: &Config,
+    opts: &BuildOptions,
+) -> Result<BuildSummary, BuildErrors> {
+    let keep = c.keep_patterns();
+    clear_old(&sibling(build_dir, "old"), &keep)?;
+    let staging = sibling(build_dir, "new");
+    let res = build_into(site_dir, &staging, c, opts).and_then(|summary| {
+        swap_into_place(&staging, build_dir, &keep)?;
+        Ok(summary)
+    });
+    if res.is_err() {
+        let _ = remove_dir_all(&staging);
+    }
+    res
+}
+
+/// `build_dir` with its name prefixed by a dot and suffixed by `suffix`, e.g. `.static.new`
+fn sibling(build_dir: &Path, suffix: &str) -> PathBuf {
+    let name = build_dir.file_name().unwrap_or_default().to_string_lossy();
+    build_dir.with_file_name(format!(".{name}.{suffix}"))
+}
+
+/// Replaces `build_dir` by `staging` and carries over the old entries matching `keep`
+fn swap_into_place(staging: &Path, build_dir: &Path, keep: &[Pattern]) -> Result<(), BuildError> {
+    let old = sibling(build_dir, "old");
+    let had_old = replace_dir(staging, build_dir, &old).map_err(io_error(build_dir))?;
+    if had_old && !keep.is_empty() {
+        // On failure the old output is left next to the new one rather than losing kept files,
+        // and `clear_old` stops later builds until they're recovered
+        carry_over(&old, build_dir, keep)?;
+    }
+    let _ = remove_dir_all(&old);
+    Ok(())
+}
+
+/// Removes the old output a previous build left behind, unless it still holds kept files
+fn clear_old(old: &Path, keep: &[Pattern]) -> Result<(), BuildError> {
+    let holds_kept = files(old, &[])
+        .iter()
+        .any(|p| is_kept(keep, &diff_paths(p, old).unwrap()));
+    if holds_kept {
+        return Err(BuildError::UnrecoveredKeep {
+            path: old.to_path_buf(),
+        });
+    }
+    let _ = remove_dir_all(old);
+    Ok(())
+}
+
+/// Whether the relative `path`, or a directory it's in, matches `keep`
+fn is_kept(keep: &[Pattern], path: &Path) -> bool {
+    path.ancestors()
+        .filter(|x| !x.as_os_str().is_empty())
+        .any(|x| glob_matches(keep, x))
+}
+
+/// Removes `build_dir`, except the entries matching `keep`
+pub fn clean(build_dir: &Path, keep: &[Pattern]) -> io::Result<()> {
+    if keep.is_empty() {
+        return remove_dir_all(build_dir);
+    }
+    let mut stack = vec![build_dir.to_path_buf()];
+    // Emptied directories are removed deepest first
+    let mut dirs = vec![];
+    while let Some(dir) = stack.pop() {
+        for entry in read_dir(&dir)? {
+            let entry = entry?;
+            let p = entry.path();
+            if glob_matches(keep, &diff_paths(&p, build_dir).unwrap()) {
+                continue;
+            }
+            if entry.file_type()?.is_dir() {
+                stack.push(p.clone());
+                dirs.push(p);
+            } else {
+                remove_file(&p)?;
+            }
+        }
+    }
+    for dir in dirs.iter().rev() {
+        // Fails for those still holding kept entries
+        let _ = remove_dir(dir);
+    }
+    Ok(())
+}
+
+/// Moves `staging` to `build_dir` and the previous `build_dir`, if any, to `old`; returns
+/// whether there was one
+///
+/// On Linux the two directories are exchanged in one `renameat2(RENAME_EXCHANGE)` call, so
+/// `build_dir` always exists and readers see either the whole old or the whole new output.
+/// Elsewhere, or on filesystems without exchange support, it falls back to two renames, between
+/// which `build_dir` briefly doesn't exist.
+fn replace_dir(staging: &Path, build_dir: &Path, old: &Path) -> io::Result<bool> {
+    #[cfg(target_os = "linux")]
+    match exchange(staging, build_dir) {
+        Ok(()) => {
+            // `staging` now holds the old output
+            if let Err(e) = rename(staging, old) {
+                let _ = exchange(staging, build_dir);
+                return Err(e);
+            }
+            return Ok(true);
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return rename(staging, build_dir).map(|_| false);
+        }
+        // EINVAL or ENOSYS: not supported here
+        Err(e) if matches!(e.raw_os_error(), Some(22 | 38)) => {}
+        Err(e) => return Err(e),
+    }
+
+    let had_old = match rename(build_dir, old) {
+        Ok(()) => true,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => false,
+        Err(e) => return Err(e),
+    };
+    if let Err(e) = rename(staging, build_dir) {
+        if had_old {
+            let _ = rename(old, build_dir);
+        }
+        return Err(e);
+    }
+    Ok(had_old)
+}
+
+/// Atomically swaps the directories `a` and `b`
+#[cfg(target_os = "linux")]
+fn exchange(a: &Path, b: &Path) -> io::Result<()> {
+    use std::{
+        ffi::{CString, c_char, c_int, c_uint},
+        os::unix::ffi::OsStrExt,
+    };
+
+    const AT_FDCWD: c_int = -100;
+    const RENAME_EXCHANGE: c_uint = 1 << 1;
+    unsafe extern "C" {
+        fn renameat2(
+            olddirfd: c_int,
+            oldpath: *const c_char,
+            newdirfd: c_int,
+            newpath: *const c_char,
+            flags: c_uint,
+        ) -> c_int;
+    }
+
+    let a = CString::new(a.as_os_str().as_bytes())?;
+    let b = CString::new(b.as_os_str().as_bytes())?;
+    // SAFETY: both paths are NUL-terminated and outlive the call
+    let res = unsafe { renameat2(AT_FDCWD, a.as_ptr(), AT_FDCWD, b.as_ptr(), RENAME_EXCHANGE) };
+    if res == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Moves the entries of `old` matching `keep` into `build_dir`, unless the build wrote them
+fn carry_over(old: &Path, build_dir: &Path, keep: &[Pattern]) -> Result<(), BuildError> {
+    let mut stack = vec![old.to_path_buf()];
+    while let Some(dir) = stack.pop() {
+        let Ok(entries) = read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let p = entry.path();
+            let relative = diff_paths(&p, old).unwrap();
+            let dest = build_dir.join(&relative);
+            if glob_matches(keep, &relative) {
+                if !dest.exists() {
+                    create_parent(&dest)?;
+                    rename(&p, &dest).map_err(io_error(&p))?;
+                }
+            } else if p.is_dir() {
+                stack.push(p);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn build_into(
+    site_dir: &Path,
+    build_dir: &Path,
+    c: &Config,
+    opts: &BuildOptions,
+) -> Result<BuildSummary, BuildErrors> {
+    let ignore = Ignore::new(site_dir, c);
+    let (collections, load_errors) = load_collections(site_dir, c, &ignore);
+    let mut errors: Vec<BuildError> = load_errors.into_iter().map(BuildError::from).collect();
+
+    let site = Site {
+        c,
+        site_dir,
+        src_dir: site_dir.join("src"),
+        build_dir,
+        components_dir: site_dir.join(&c.components_dir),
+        static_dir: c.static_dir.as_ref().map(|x| site_dir.join(x)),
+        styles_css: site_dir.join(&c.styles_css),
+        series: collect_series(&collections),
+        related: c
+            .related
+            .as_ref()
+            .map(|x| related_posts(&collections, x.count))
+            .unwrap_or_default(),
+        collections,
+        ignore,
+        cache: opts.cache.then(|| RenderCache::new(site_dir)),
+    };
+
+    // Discovery
+    let (sources, discover_errors) = discover(&site);
+    errors.extend(discover_errors);
+
+    // Rendering; pandoc runs dominate, so posts are spread over `jobs` threads
+    let mut outputs = parallel_map(&sources, opts.jobs, |p| render(p, &site));
+
+    outputs.extend(site.series.iter().map(|x| render_series_index(x, &site)));
+    if let Some(hc) = &c.highlight {
+        outputs.push(Ok(Output::Write {
+            dest: build_dir.join(&hc.stylesheet),
+            contents: stylesheet(hc),
+            feed_item: None,
+            links: None,
+        }));
+    }
+
+    // Copied verbatim after the rendered sources, so they win on collisions (which `check` reports)
+    if let Some(static_dir) = &site.static_dir {
+        let static_files = files(static_dir, &[])
+            .into_iter()
+            .filter(|p| !site.ignore.is_ignored(p));
+        outputs.extend(static_files.map(|src| {
+            let dest = build_dir.join(diff_paths(&src, static_dir).unwrap());
+            Ok(Output::Copy { src, dest })
+        }));
+    }
+
+    // Link graph; backlinks are known only once every post is rendered.
+    // Every post links, but only those whose layout has `{BACKLINKS}` are linked to
+    let graph = link_graph(outputs.iter().filter_map(|x| match x {
+        Ok(Output::Write {
+            links: Some((post, targets)),
+            ..
+        }) => Some((&***post, targets.as_slice())),
+        _ => None,
+    }));
+    let backlinks = outputs
+        .iter()
+        .map(|x| match (x, &c.backlinks) {
+            (
+                Ok(Output::Write {
+                    dest,
+                    contents,
+                    links: Some((post, _)),
+                    ..
+                }),
+                Some(bc),
+            ) if contents.contains(MARKER) => render_items(
+                &bc.with,
+                &site.components_dir,
+                graph.get(&normalize(dest)).into_iter().flatten().copied(),
+                dest,
+                build_dir,
+                c,
+            )
+            .map_err(|e| BuildError::Html {
+                path: post.path.clone(),
+                source: e,
+            }),
+            _ => Ok(String::new()),
+        })
+        .collect::<Vec<_>>();
+    for (output, backlinks) in outputs.iter_mut().zip(backlinks) {
+        match (output, backlinks) {
+            (Ok(Output::Write { contents, .. }), Ok(backlinks)) if contents.contains(MARKER) => {
+                *contents = contents.replace(MARKER, &backlinks);
+            }
+            (output, Err(e)) => *output = Err(e),
+            _ => {}
+        }
+    }
+
+    // Writing, in discovery order so the result doesn't depend on thread timing.
+    // `build_dir` is the staging directory; leftovers of an interrupted build are discarded
+    let _ = remove_dir_all(build_dir);
+    create_dir(build_dir).map_err(io_error(build_dir))?;
+
+    let mut feeds: HashMap<&str, RssData> = site
+        .collections
+        .iter()
+        .filter(|x| x.feed)
+        .map(|x| {
+            let data = RssData::new(Some(RssVersion::RSS2_0))
+                .title(x.feed_title(c))
+                .link(c.site_url())
+                .description(&c.description);
+            (x.name.as_str(), data)
+        })
+        .collect();
+
+    for output in outputs {
+        let res = output.and_then(|output| match output {
+            Output::Write {
+                dest,
+                contents,
+                feed_item,
+                ..
+            } => {
+                if let Some((name, item)) = feed_item
+                    && let Some(rss_data) = feeds.get_mut(name)
+                {
+                    rss_data.add_item(*item);
+                }
+                create_parent(&dest)?;
+                write(&dest, contents).map_err(io_error(&dest))
+            }
+            Output::Copy { src, dest } => {
+                create_parent(&dest)?;
+                copy(&src, &dest).map(|_| ()).map_err(io_error(&src))
+            }
+            Output::Skip => Ok(()),
+        });
+        if let Err(e) = res {
+            errors.push(e);
+        }
+    }
+
+    for x in site.collections.iter().filter(|x| x.feed) {
+        let path = build_dir.join(x.feed_file());
+        let res = generate_rss(&feeds[x.name.as_str()])
+            .map_err(|e| BuildError::Feed {
+                path: path.clone(),
+                source: e,
+            })
+            .and_then(|rss| write(&path, rss).map_err(io_error(&path)));
+        if let Err(e) = res {
+            errors.push(e);
+        }
+    }
+
+    if let Err(e) = write_redirects(build_dir, &collect_redirects(&site.collections, c), c) {
+        errors.push(e.into());
+    }
+
+    if errors.is_empty() {
+        let count = |ext: &str| {
+            sources
+                .iter()
+                .filter(|p| p.extension().is_some_and(|x| x == ext))
+                .count()
+        };
+        Ok(BuildSummary {
+            pages: count("html"),
+            posts: count("md"),
+            cache_hits: site.cache.as_ref().map_or(0, |x| x.hits()),
+            cache_misses: site.cache.as_ref().map_or(0, |x| x.misses()),
+        })
+    } else {
+        Err(BuildErrors(errors))
+    }
+}
+
+/// Every file below `src/` the build processes
+fn discover(site: &Site) -> (Vec<PathBuf>, Vec<BuildError>) {
+    let blacklist = [Some(site.components_dir.clone()), site.static_dir.clone()];
+    let mut sources = vec![];
+    let mut errors = vec![];
+
+    let mut stack = vec![site.src_dir.clone()];
+    while let Some(path) = stack.pop() {
+        let entries = match read_dir(&path) {
+            Ok(entries) => entries,
+            Err(e) => {
+                errors.push(io_error(&path)(e));
+                continue;
+            }
+        };
+        for entry in entries {
+            let p = match entry {
+                Ok(entry) => entry.path(),
+                Err(e) => {
+                    errors.push(io_error(&path)(e));
+                    continue;
+                }
+            };
+
+            if blacklist.iter().flatten().any(|x| x == &p) || site.ignore.is_ignored(&p) {
+                continue;
+            }
+            if p.is_dir() {
+                stack.push(p);
+            } else {
+                sources.push(p);
+            }
+        }
+    }
+    // `read_dir` order varies between filesystems
+    sources.sort();
+    (sources, errors)
+}
+
+/// Files that differ between two builds, relative to their roots, leaving out kept files
+pub fn diff_builds(a: &Path, b: &Path, keep: &[Pattern]) -> Vec<String> {
+    // Kept files are carried over from earlier builds, so only the real output dir has them
+    let relative = |dir: &Path| {
+        files(dir, &[])
+            .into_iter()
+            .map(|p| diff_paths(&p, dir).unwrap())
+            .filter(|p| !is_kept(keep, p))
+            .collect::<BTreeSet<_>>()
+    };
+    let (files_a, files_b) = (relative(a), relative(b));
+
+    let mut res = vec![];
+    for p in files_a.union(&files_b) {
+        let problem = match (files_a.contains(p), files_b.contains(p)) {
+            (true, false) => "only in first build",
+            (false, true) => "only in second build",
+            _ if read(a.join(p)).ok() != read(b.join(p)).ok() => "differs",
+            _ => continue,
+        };
+        res.push(format!("{problem}: {}", p.display()));
+    }
+    res
+}
+
+/// All files below `dir`, skipping `blacklist`ed directories; empty if `dir` doesn't exist
+pub fn files(dir: &Path, blacklist: &[PathBuf]) -> Vec<PathBuf> {
+    let mut res = vec![];
+    let mut stack = vec![dir.to_path_buf()];
+    while let Some(path) = stack.pop() {
+        let Ok(entries) = read_dir(path) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let p = entry.path();
+            if blacklist.contains(&p) {
+                continue;
+            }
+            if p.is_dir() {
+                stack.push(p);
+            } else {
+                res.push(p);
+            }
+        }
+    }
+    res.sort();
+    res
+}
+
+/// Maps `f` over `items` on `jobs` threads, keeping the results in order
+fn parallel_map<T: Sync, R: Send>(items: &[T], jobs: usize, f: impl Fn(&T) -> R + Sync) -> Vec<R> {
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new((0..items.len()).map(|_| None).collect::<Vec<Option<R>>>());
+
+    thread::scope(|s| {
+        for _ in 0..jobs.clamp(1, items.len().max(1)) {
+            s.spawn(|| {
+                loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(item) = items.get(i) else {
+                        break;
+                    };
+                    let r = f(item);
+                    results.lock().unwrap()[i] = Some(r);
+                }
+            });
+        }
+    });
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(Option::unwrap)
+        .collect()
+}
+
+fn render<'a>(p: &Path, site: &'a Site) -> Result<Output<'a>, BuildError> {
+    match p.extension().and_then(|s| s.to_str()) {
+        Some("html") => render_page(p, site),
+        Some("md") => render_post(p, site),
+        // Everything else, including extension-less files like CNAME, is copied
+        _ => Ok(Output::Copy {
+            src: p.to_path_buf(),
+            dest: mirrored_dest(p, site),
+        }),
+    }
+}
+
+fn mirrored_dest(p: &Path, site: &Site) -> PathBuf {
+    site.build_dir.join(diff_paths(p, &site.src_dir).unwrap())
+}
+
+fn create_parent(dest: &Path) -> Result<(), BuildError> {
+    let parent = dest.parent().unwrap();
+    create_dir_all(parent).map_err(io_error(parent))
+}
+
+fn render_page<'a>(p: &Path, site: &'a Site) -> Result<Output<'a>, BuildError> {
+    let dest = mirrored_dest(p, site);
+    let contents = generate_substituted_html(
+        p,
+        &dest,
+        site.build_dir,
+        &site.collections,
+        &site.components_dir,
+        site.c,
+    )
+    .map_err(|e| BuildError::Html {
+        path: p.to_path_buf(),
+        source: e,
+    })?;
+    Ok(Output::Write {
+        contents: highlight(&contents, &dest, site),
+        dest,
+        feed_item: None,
+        links: None,
+    })
+}
+
+/// Highlights the code blocks of a page written to `dest`, when the site asks for it
+fn highlight(html: &str, dest: &Path, site: &Site) -> String {
+    match &site.c.highlight {
+        Some(hc) => add_stylesheet_link(
+            &highlight_html(html, hc),
+            dest,
+            &site.build_dir.join(&hc.stylesheet),
+        ),
+        None => html.to_owned(),
+    }
+}
+
+/// Page listing every part of a series
+fn render_series_index<'a>(series: &Series, site: &'a Site) -> Result<Output<'a>, BuildError> {
+    let c = site.c;
+    let dest = site.build_dir.join(permalink::output_path(&series.url));
+    let list = series_index(series, &dest, site.build_dir);
+    let styles = diff_paths(
+        site.build_dir
+            .join(diff_paths(&site.styles_css, &site.src_dir).unwrap()),
+        dest.parent().unwrap(),
+    )
+    .unwrap()
+    .to_string_lossy()
+    .to_string();
+
+    let layout = c
+        .series_layout
+        .as_ref()
+        .ok_or_else(|| BuildError::NoSeriesLayout {
+            path: series.parts[0].path.clone(),
+            series: series.name.clone(),
+        })?;
+    let html = render_layout(
+        layout,
+        &site.components_dir,
+        HashMap::from([
+            ("TITLE", series.name.clone()),
+            ("CONTENT", list),
+            ("STYLES", styles),
+        ]),
+    )
+    .map_err(|e| BuildError::Html {
+        path: site.components_dir.join(layout),
+        source: e,
+    })?;
+    let html = add_rss_meta(&html, &site.collections, c);
+    Ok(Output::Write {
+        dest,
+        contents: add_base_path(&html, &c.base_path()),
+        feed_item: None,
+        links: None,
+    })
+}
+
+/// Anchors the headings of a rendered post and builds its table of contents, which is also
+/// inserted into the post when its front matter asks for it
+fn add_toc(html: &str, md_info: &MdInfo, c: &Config) -> (String, String) {
+    let (html, headings) = anchor_headings(html);
+    let toc = toc_html(&headings, &c.toc);
+    if md_info.toc && !toc.is_empty() {
+        (insert_toc(&html, &toc, &headings), toc)
+    } else {
+        (html, toc)
+    }
+}
+
+/// Renders a markdown post, along with its feed item if it belongs to a collection
+fn render_post<'a>(p: &Path, site: &'a Site) -> Result<Output<'a>, BuildError> {
+    let c = site.c;
+    let styles_css = site
+        .build_dir
+        .join(diff_paths(&site.styles_css, &site.src_dir).unwrap());
+
+    let collection = collection::containing(&site.collections, p);
+    let md_info = match collection {
+        Some(x) => match x.posts.iter().find(|c| c.path == p) {
+            Some(info) => Cow::Borrowed(info),
+            None => return Ok(Output::Skip),
+        },
+        None => {
+            let mut info = get_md_info(p)?;
+            info.url = permalink::mirrored(p, &site.src_dir);
+            Cow::Owned(info)
+        }
+    };
+    let dest = site.build_dir.join(permalink::output_path(&md_info.url));
+
+    let source = read_to_string(p).map_err(|e| MdError::Io {
+        path: p.to_path_buf(),
+        source: e,
+    })?;
+    let ctx = LinkContext {
+        src_dir: &site.src_dir,
+        build_dir: site.build_dir,
+        dest: &dest,
+        collections: &site.collections,
+    };
+    let mut source = rewrite_links(p, &source, &ctx)?;
+    if c.highlight.is_some() {
+        source = rewrite_fences(&source);
+    }
+    let pandoc = c.pandoc.merge(&md_info.pandoc).resolve(site.site_dir);
+    let math = pandoc.math.unwrap_or_default();
+
+    let (html, links) = match collection.and_then(|x| x.layout.as_ref()) {
+        Some(layout) => {
+            let opts = RenderOptions {
+                pandoc,
+                highlight: c.highlight.is_some(),
+                ..Default::default()
+            };
+            let content = render_to_html(p, &source, &dest, &opts, site.cache.as_ref())?;
+            let (content, toc) = add_toc(&content, &md_info, c);
+            let links = internal_links(&content, &dest, site.build_dir, c);
+            // Within the collection, by date; empty for the first and last post
+            let (prev, next) = collection.map_or((None, None), |x| x.neighbours(&md_info));
+            let link = |x: Option<&MdInfo>| {
+                x.map_or(String::new(), |x| {
+                    permalink::relative_link(&dest, site.build_dir, &x.url)
+                })
+            };
+            let title = |x: Option<&MdInfo>| x.map_or(String::new(), |x| x.title.clone());
+            let related = match (&c.related, site.related.get(p)) {
+                (Some(rc), Some(posts)) => render_items(
+                    &rc.with,
+                    &site.components_dir,
+                    posts,
+                    &dest,
+                    site.build_dir,
+                    c,
+                )
+                .map_err(|e| BuildError::Html {
+                    path: p.to_path_buf(),
+                    source: e,
+                })?,
+                _ => String::new(),
+            };
+            let styles = diff_paths(&styles_css, dest.parent().unwrap())
+                .unwrap()
+                .to_string_lossy()
+                .to_string();
+            let html = render_layout(
+                layout,
+                &site.components_dir,
+                HashMap::from([
+                    ("TITLE", md_info.title.clone()),
+                    ("DATE", md_info.date.format("%A %d %B %Y").to_string()),
+                    ("MATH", math_head(math, &content)),
+                    ("CONTENT", content),
+                    ("TOC", toc),
+                    ("PREV_URL", link(prev)),
+                    ("PREV_TITLE", title(prev)),
+                    ("NEXT_URL", link(next)),
+                    ("NEXT_TITLE", title(next)),
+                    (
+                        "SERIES_NAV",
+                        series::find(&site.series, &md_info).map_or(String::new(), |x| {
+                            series_nav(x, &md_info, &dest, site.build_dir)
+                        }),
+                    ),
+                    ("WORDS", md_info.words.to_string()),
+                    ("READING_TIME", md_info.reading_minutes(c).to_string()),
+                    ("RELATED", related),
+                    ("BACKLINKS", MARKER.to_owned()),
+                    ("STYLES", styles),
+                ]),
+            )
+            .map_err(|e| BuildError::Html {
+                path: p.to_path_buf(),
+                source: e,
+            })?;
+            (html, links)
+        }
+        None => {
+            let opts = RenderOptions {
+                css_path: Some(&styles_css),
+                standalone: true,
+                pandoc,
+                highlight: c.highlight.is_some(),
+                ..Default::default()
+            };
+            let html = render_to_html(p, &source, &dest, &opts, site.cache.as_ref())?;
+            let html = add_toc(&html, &md_info, c).0;
+            let links = internal_links(&html, &dest, site.build_dir, c);
+            (html, links)
+        }
+    };
+    let html = highlight(&html, &dest, site);
+
+    let post_url = c.site_url() + "/" + &md_info.url;
+
+    let html = add_rss_meta(
+        &add_meta_to_post_html(html, &md_info, &post_url, &c.og_image_url, &c.site_name),
+        &site.collections,
+        c,
+    );
+
+    let feed_item = collection.map(|x| {
+        let item = RssItem::new()
+            .title(&md_info.title)
+            .description(truncate_content(&md_info.content, 80))
+            .guid(&post_url)
+            .pub_date(
+                DateTime::<Utc>::from_naive_utc_and_offset(
+                    md_info.date.and_hms_opt(0, 0, 0).unwrap(),
+                    Utc,
+                )
+                .to_rfc2822(),
+            )
+            .link(&post_url);
+        (x.name.as_str(), Box::new(item))
+    });
+    Ok(Output::Write {
+        dest,
+        contents: add_base_path(&html, &c.base_path()),
+        feed_item,
+        links: Some((Box::new(md_info), links)),
+    })
+}
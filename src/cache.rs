@@ -0,0 +1,102 @@
+use std::{
+    fs::{create_dir_all, read, read_to_string, rename, write},
+    path::{Path, PathBuf},
+    process::Command,
+    sync::{
+        OnceLock,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use sha2::{Digest, Sha256};
+
+/// Directory below the site root holding rendered post HTML
+pub const CACHE_DIR: &str = ".ssg-cache";
+
+/// On-disk cache of pandoc output, keyed by everything that affects it
+pub struct RenderCache {
+    dir: PathBuf,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+}
+
+impl RenderCache {
+    pub fn new(site_dir: &Path) -> Self {
+        RenderCache {
+            dir: site_dir.join(CACHE_DIR),
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0),
+        }
+    }
+
+    /// Hash of the markdown source, the pandoc arguments and version, the contents of the
+    /// `files` they name, and this generator's version
+    pub fn key<'a>(
+        &self,
+        source: &str,
+        args: impl Iterator<Item = &'a std::ffi::OsStr>,
+        files: &[&PathBuf],
+    ) -> String {
+        let mut h = Sha256::new();
+        h.update(env!("CARGO_PKG_VERSION"));
+        h.update([0]);
+        h.update(pandoc_version());
+        for arg in args {
+            h.update([0]);
+            h.update(arg.as_encoded_bytes());
+        }
+        for file in files {
+            h.update([0]);
+            h.update(read(file).unwrap_or_default());
+        }
+        h.update([0]);
+        h.update(source);
+        h.finalize().iter().map(|b| format!("{b:02x}")).collect()
+    }
+
+    pub fn get(&self, key: &str) -> Option<String> {
+        match read_to_string(self.dir.join(key)) {
+            Ok(html) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Some(html)
+            }
+            Err(_) => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    /// Best effort; a cache that can't be written only costs a re-render next time
+    pub fn put(&self, key: &str, html: &str) {
+        let tmp = self.dir.join(format!("{key}.tmp"));
+        let _ = create_dir_all(&self.dir)
+            .and_then(|_| write(&tmp, html))
+            .and_then(|_| rename(&tmp, self.dir.join(key)));
+    }
+
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    pub fn misses(&self) -> usize {
+        self.misses.load(Ordering::Relaxed)
+    }
+}
+
+fn pandoc_version() -> &'static str {
+    static VERSION: OnceLock<String> = OnceLock::new();
+    VERSION.get_or_init(|| {
+        Command::new("pandoc")
+            .arg("--version")
+            .output()
+            .map(|x| {
+                String::from_utf8_lossy(&x.stdout)
+                    .lines()
+                    .next()
+                    .unwrap_or_default()
+                    .to_owned()
+            })
+            .unwrap_or_default()
+    })
+}
@@ -0,0 +1,304 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fs::{read_to_string, remove_dir_all},
+    path::{Path, PathBuf},
+};
+
+use pathdiff::diff_paths;
+use regex::Regex;
+
+use crate::{
+    build::{BuildErrors, BuildOptions, build, files},
+    collection::{self, Collection, load_collections},
+    config::Config,
+    ignore::Ignore,
+    links::normalize,
+    permalink::{self, output_path},
+    redirect::collect_redirects,
+    series::collect_series,
+};
+
+/// Builds the site into a temporary directory and returns every problem found in it
+pub fn check(site_dir: &Path, c: &Config) -> std::result::Result<Vec<String>, BuildErrors> {
+    let src_dir = site_dir.join("src");
+    let components_dir = site_dir.join(&c.components_dir);
+    // Posts that fail to load are reported by the build below
+    let ignore = Ignore::new(site_dir, c);
+    let (collections, _) = load_collections(site_dir, c, &ignore);
+
+    let (missing, unused) = check_components(&src_dir, &components_dir, &ignore, c);
+    let mut problems = missing;
+    let missing_components = !problems.is_empty();
+    problems.extend(unused);
+    problems.extend(check_duplicate_outputs(
+        site_dir,
+        &src_dir,
+        &components_dir,
+        &collections,
+        &ignore,
+        c,
+    ));
+    if missing_components {
+        // The build would fail on them, so report what we have
+        return Ok(problems);
+    }
+
+    let build_dir = std::env::temp_dir().join(format!("ssg-check-{}", std::process::id()));
+    let res = build(site_dir, &build_dir, c, &BuildOptions::default());
+    let report = res.map(|_| {
+        let (links, targets) = check_links(&build_dir, c);
+        problems.extend(links);
+        for x in &collections {
+            let attachments = files(&x.dir.join("attachments"), &[]);
+            for attachment in attachments.iter().filter(|p| !ignore.is_ignored(p)) {
+                let out = build_dir.join(diff_paths(attachment, &src_dir).unwrap());
+                if !targets.contains(&normalize(&out)) {
+                    problems.push(format!("orphaned attachment: {}", attachment.display()));
+                }
+            }
+        }
+        problems
+    });
+    let _ = remove_dir_all(&build_dir);
+    report
+}
+
+/// `<REPLACE>`/`<FEED>` components that don't exist, and components nothing uses
+fn check_components(
+    src_dir: &Path,
+    components_dir: &Path,
+    ignore: &Ignore,
+    c: &Config,
+) -> (Vec<String>, Vec<String>) {
+    let re = Regex::new(r#"<(?:REPLACE|FEED)\b[^>]*\bwith="([^"]*)""#).unwrap();
+    let mut missing = vec![];
+    let mut unused = vec![];
+    let mut used = BTreeSet::new();
+
+    for p in files(src_dir, &[]) {
+        if p.extension().is_none_or(|x| x != "html") || ignore.is_ignored(&p) {
+            continue;
+        }
+        let contents = read_to_string(&p).unwrap_or_default();
+        for caps in re.captures_iter(&contents) {
+            let component = components_dir.join(&caps[1]);
+            if !component.is_file() {
+                missing.push(format!(
+                    "missing component: {} (used in {})",
+                    &caps[1],
+                    p.display()
+                ));
+            }
+            used.insert(component);
+        }
+    }
+    // Components named in config.json
+    let mut configured = c
+        .collections()
+        .into_iter()
+        .filter_map(|(name, cc)| Some((cc.layout?, format!("layout of collection '{name}'"))))
+        .collect::<Vec<_>>();
+    if let Some(layout) = &c.series_layout {
+        configured.push((layout.clone(), "`series_layout`".to_owned()));
+    }
+    if let Some(rc) = &c.related {
+        configured.push((rc.with.clone(), "`related` item".to_owned()));
+    }
+    if let Some(bc) = &c.backlinks {
+        configured.push((bc.with.clone(), "`backlinks` item".to_owned()));
+    }
+    for (layout, role) in configured {
+        let component = components_dir.join(&layout);
+        if !component.is_file() {
+            missing.push(format!("missing component: {} ({role})", layout.display()));
+        }
+        used.insert(component);
+    }
+    for p in files(components_dir, &[]) {
+        if !used.contains(&p) {
+            unused.push(format!("unused component: {}", p.display()));
+        }
+    }
+    (missing, unused)
+}
+
+/// Output files more than one source (or redirect) would write
+fn check_duplicate_outputs(
+    site_dir: &Path,
+    src_dir: &Path,
+    components_dir: &Path,
+    collections: &[Collection],
+    ignore: &Ignore,
+    c: &Config,
+) -> Vec<String> {
+    let mut outputs: BTreeMap<PathBuf, Vec<String>> = BTreeMap::new();
+
+    let static_dir = c.static_dir.as_ref().map(|x| site_dir.join(x));
+    let blacklist = [Some(components_dir.to_path_buf()), static_dir.clone()];
+    for p in files(
+        src_dir,
+        &blacklist.into_iter().flatten().collect::<Vec<_>>(),
+    )
+    .into_iter()
+    .filter(|p| !ignore.is_ignored(p))
+    {
+        let out = match p.extension().and_then(|s| s.to_str()) {
+            Some("md") => collection::containing(collections, &p)
+                .and_then(|x| x.posts.iter().find(|post| post.path == p))
+                .map(|post| output_path(&post.url))
+                .unwrap_or_else(|| PathBuf::from(permalink::mirrored(&p, src_dir))),
+            _ => diff_paths(&p, src_dir).unwrap(),
+        };
+        outputs
+            .entry(normalize(&out))
+            .or_default()
+            .push(p.display().to_string());
+    }
+    if let Some(static_dir) = &static_dir {
+        for p in files(static_dir, &[])
+            .into_iter()
+            .filter(|p| !ignore.is_ignored(p))
+        {
+            outputs
+                .entry(diff_paths(&p, static_dir).unwrap())
+                .or_default()
+                .push(p.display().to_string());
+        }
+    }
+    if let Some(hc) = &c.highlight {
+        outputs
+            .entry(normalize(&hc.stylesheet))
+            .or_default()
+            .push("highlight stylesheet".to_owned());
+    }
+    for x in collect_series(collections) {
+        outputs
+            .entry(normalize(&output_path(&x.url)))
+            .or_default()
+            .push(format!("index of series '{}'", x.name));
+    }
+    for x in collections.iter().filter(|x| x.feed) {
+        outputs
+            .entry(PathBuf::from(x.feed_file()))
+            .or_default()
+            .push(format!("feed of collection '{}'", x.name));
+    }
+    for r in collect_redirects(collections, c) {
+        outputs
+            .entry(normalize(&output_path(&r.from)))
+            .or_default()
+            .push(format!("redirect to {}", r.to));
+    }
+
+    outputs
+        .into_iter()
+        .filter(|(_, sources)| sources.len() > 1)
+        .map(|(out, sources)| {
+            format!(
+                "duplicate output: {} ({})",
+                out.display(),
+                sources.join(", ")
+            )
+        })
+        .collect()
+}
+
+/// Broken internal links and anchors, plus the set of files that are linked to
+fn check_links(build_dir: &Path, c: &Config) -> (Vec<String>, BTreeSet<PathBuf>) {
+    let link_re = Regex::new(r#"\b(?:href|src)=(?:"([^"]*)"|'([^']*)')"#).unwrap();
+    let scheme = Regex::new(r"^[[:alpha:]][[:alnum:]+.-]*:").unwrap();
+    let site_url = c.site_url();
+    let base_path = c.base_path();
+
+    let pages = files(build_dir, &[])
+        .into_iter()
+        .filter(|p| p.extension().is_some_and(|x| x == "html"))
+        .map(|p| {
+            let contents = read_to_string(&p).unwrap_or_default();
+            (normalize(&p), contents)
+        })
+        .collect::<HashMap<PathBuf, String>>();
+    let mut ids: HashMap<PathBuf, BTreeSet<String>> = HashMap::new();
+
+    let mut problems = vec![];
+    let mut targets = BTreeSet::new();
+    let mut sorted_pages = pages.keys().collect::<Vec<_>>();
+    sorted_pages.sort();
+
+    for page in sorted_pages {
+        let page_name = diff_paths(page, normalize(build_dir)).unwrap();
+        for caps in link_re.captures_iter(&pages[page]) {
+            let raw = caps.get(1).or_else(|| caps.get(2)).unwrap().as_str();
+            let url = raw.replace("&amp;", "&");
+            let url = match url.strip_prefix(&site_url) {
+                Some(rest) if rest.is_empty() || rest.starts_with('/') => {
+                    format!("{base_path}/{}", rest.trim_start_matches('/'))
+                }
+                _ => url,
+            };
+            if url.starts_with("//") || scheme.is_match(&url) {
+                continue;
+            }
+
+            let (path, fragment) = match url.find(['#', '?']) {
+                Some(i) => (&url[..i], url[i..].strip_prefix('#').unwrap_or("")),
+                None => (url.as_str(), ""),
+            };
+            let target = if path.is_empty() {
+                page.clone()
+            } else if let Some(rooted) = path.strip_prefix('/') {
+                match rooted.strip_prefix(base_path.trim_start_matches('/')) {
+                    Some(rest)
+                        if rest.is_empty() || rest.starts_with('/') || base_path.is_empty() =>
+                    {
+                        normalize(&build_dir.join(rest.trim_start_matches('/')))
+                    }
+                    _ => {
+                        problems.push(format!(
+                            "link outside base path in {}: {raw}",
+                            page_name.display()
+                        ));
+                        continue;
+                    }
+                }
+            } else {
+                normalize(&page.parent().unwrap().join(path))
+            };
+            let target = if target.is_dir() || path.ends_with('/') {
+                target.join("index.html")
+            } else {
+                target
+            };
+
+            if !target.is_file() {
+                problems.push(format!("broken link in {}: {raw}", page_name.display()));
+                continue;
+            }
+            if !fragment.is_empty()
+                && let Some(contents) = pages.get(&target)
+            {
+                let target_ids = ids
+                    .entry(target.clone())
+                    .or_insert_with(|| anchor_ids(contents));
+                if !target_ids.contains(fragment) {
+                    problems.push(format!("broken anchor in {}: {raw}", page_name.display()));
+                }
+            }
+            targets.insert(target);
+        }
+    }
+    (problems, targets)
+}
+
+fn anchor_ids(contents: &str) -> BTreeSet<String> {
+    let re = Regex::new(r#"\b(?:id|name)=(?:"([^"]*)"|'([^']*)')"#).unwrap();
+    re.captures_iter(contents)
+        .map(|caps| {
+            caps.get(1)
+                .or_else(|| caps.get(2))
+                .unwrap()
+                .as_str()
+                .to_owned()
+        })
+        .collect()
+}
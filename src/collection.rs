@@ -0,0 +1,93 @@
+use std::path::{Path, PathBuf};
+
+use crate::{
+    config::{Config, DEFAULT_COLLECTION},
+    ignore::Ignore,
+    markdown::{MdError, MdInfo, get_mdinfos_for_path},
+    permalink,
+};
+
+#[derive(Debug, Clone)]
+pub struct Collection {
+    pub name: String,
+    pub dir: PathBuf,
+    pub layout: Option<PathBuf>,
+    pub feed: bool,
+    /// Sorted newest first
+    pub posts: Vec<MdInfo>,
+}
+
+impl Collection {
+    /// Feed location relative to the output root
+    pub fn feed_file(&self) -> String {
+        if self.name == DEFAULT_COLLECTION {
+            "feed.xml".to_owned()
+        } else {
+            format!("{}.xml", self.name)
+        }
+    }
+
+    /// The posts dated just before and just after `post`
+    pub fn neighbours(&self, post: &MdInfo) -> (Option<&MdInfo>, Option<&MdInfo>) {
+        match self.posts.iter().position(|x| x.path == post.path) {
+            // Newest first, so the previous post comes after it
+            Some(i) => (
+                self.posts.get(i + 1),
+                i.checked_sub(1).map(|i| &self.posts[i]),
+            ),
+            None => (None, None),
+        }
+    }
+
+    pub fn feed_title(&self, c: &Config) -> String {
+        if self.name == DEFAULT_COLLECTION {
+            c.site_name.clone()
+        } else {
+            format!("{} - {}", c.site_name, self.name)
+        }
+    }
+}
+
+/// Loads every collection; posts that fail to load are left out and returned as errors
+pub fn load_collections(
+    site_dir: &Path,
+    c: &Config,
+    ignore: &Ignore,
+) -> (Vec<Collection>, Vec<MdError>) {
+    let src_dir = site_dir.join("src");
+    let mut res = vec![];
+    let mut errors = vec![];
+    for (name, cc) in c.collections() {
+        let dir = site_dir.join(&cc.dir);
+        let (mut posts, post_errors) = get_mdinfos_for_path(&dir, ignore);
+        errors.extend(post_errors);
+        for post in posts.iter_mut() {
+            post.url = match &cc.permalink {
+                Some(pattern) => permalink::expand(pattern, post, &name),
+                None => permalink::mirrored(&post.path, &src_dir),
+            };
+        }
+        // Newest first; posts from the same day keep their title/path order
+        posts.sort_by(|a, b| b.date.cmp(&a.date).then_with(|| a.cmp(b)));
+        res.push(Collection {
+            name,
+            dir,
+            layout: cc.layout,
+            feed: cc.feed,
+            posts,
+        });
+    }
+    (res, errors)
+}
+
+pub fn find<'a>(collections: &'a [Collection], name: &str) -> Option<&'a Collection> {
+    collections.iter().find(|x| x.name == name)
+}
+
+/// Innermost collection whose directory contains `path`
+pub fn containing<'a>(collections: &'a [Collection], path: &Path) -> Option<&'a Collection> {
+    collections
+        .iter()
+        .filter(|x| path.starts_with(&x.dir))
+        .max_by_key(|x| x.dir.components().count())
+}
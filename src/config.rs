@@ -1,20 +1,349 @@
 use std::{
+    collections::BTreeMap,
     fs::read_to_string,
     io,
     path::{Path, PathBuf},
 };
 
-use serde::{Deserialize, Serialize};
+use glob::{MatchOptions, Pattern};
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::{highlight, ignore, links::normalize};
+
+/// Name of the collection built from `posts_dir` when no `collections` are configured.
+pub const DEFAULT_COLLECTION: &str = "posts";
 
 #[derive(Deserialize, Serialize, Debug)]
 pub struct Config {
     pub styles_css: PathBuf,
     pub components_dir: PathBuf,
+    #[serde(default = "default_posts_dir")]
     pub posts_dir: PathBuf,
     pub hosted_url: String,
     pub og_image_url: String,
     pub site_name: String,
     pub description: String,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub collections: BTreeMap<String, CollectionConfig>,
+    /// Default permalink pattern for collections without their own
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub permalink: Option<String>,
+    /// Old location -> new URL, either site-relative or absolute
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub redirects: BTreeMap<String, String>,
+    #[serde(default)]
+    pub redirect_files: RedirectFiles,
+    /// Sub-path the site is served under, e.g. `/project`
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub base_path: String,
+    /// Glob patterns, relative to the output dir, of files a build never deletes (e.g. `.git`, `CNAME`)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keep: Vec<String>,
+    /// Glob patterns of sources to skip, on top of those in `.ssgignore`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ignore: Vec<String>,
+    /// Directory whose contents are copied verbatim to the output root
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub static_dir: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "PandocConfig::is_empty")]
+    pub pandoc: PandocConfig,
+    #[serde(default, skip_serializing_if = "TocConfig::is_default")]
+    pub toc: TocConfig,
+    /// Component wrapping generated series index pages, required once a post has a `series`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub series_layout: Option<PathBuf>,
+    /// Reading speed behind `{READING_TIME}`
+    #[serde(default = "default_words_per_minute")]
+    pub words_per_minute: usize,
+    /// Reading speed for Chinese and Japanese text, whose characters count as words
+    #[serde(default = "default_cjk_chars_per_minute")]
+    pub cjk_chars_per_minute: usize,
+    /// Build-time syntax highlighting of code blocks; pandoc's own highlighting is used when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub highlight: Option<HighlightConfig>,
+    /// `{RELATED}` lists of similar posts in post layouts; empty when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub related: Option<RelatedConfig>,
+    /// `{BACKLINKS}` lists of the posts linking to a post; empty when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backlinks: Option<BacklinksConfig>,
+}
+
+/// Tables of contents built from the headings of posts
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct TocConfig {
+    /// Heading levels listed, counted from the top-most heading of the post
+    #[serde(default = "default_toc_depth")]
+    pub depth: usize,
+    /// Posts with fewer headings get an empty table of contents
+    #[serde(default = "default_min_headings")]
+    pub min_headings: usize,
+}
+
+impl Default for TocConfig {
+    fn default() -> Self {
+        TocConfig {
+            depth: default_toc_depth(),
+            min_headings: default_min_headings(),
+        }
+    }
+}
+
+impl TocConfig {
+    fn is_default(&self) -> bool {
+        self == &TocConfig::default()
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct HighlightConfig {
+    /// One of syntect's bundled themes, e.g. `InspiredGitHub` or `base16-ocean.dark`
+    #[serde(default = "default_theme")]
+    pub theme: String,
+    /// Number the lines of every block, not only those with a `numberLines` class
+    #[serde(default)]
+    pub line_numbers: bool,
+    /// Generated theme stylesheet, relative to the output dir
+    #[serde(default = "default_highlight_css")]
+    pub stylesheet: PathBuf,
+}
+
+/// Posts listed by `{RELATED}`, ranked by shared tags, then by how alike their text is
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct RelatedConfig {
+    /// Item component each post goes through, as with `<FEED>`
+    pub with: PathBuf,
+    #[serde(default = "default_related_count")]
+    pub count: usize,
+}
+
+/// Posts listed by `{BACKLINKS}`, oldest first
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct BacklinksConfig {
+    /// Item component each post goes through, as with `<FEED>`
+    pub with: PathBuf,
+}
+
+/// How posts are converted; set site-wide and overridable per post by a `pandoc:` front matter key.
+/// Paths are relative to the site root.
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct PandocConfig {
+    /// Input format with extensions, e.g. `markdown+smart+emoji`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from: Option<String>,
+    /// Passed to pandoc after every other argument
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub args: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lua_filters: Option<Vec<PathBuf>>,
+    /// HTML template for standalone pages
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub highlight_style: Option<String>,
+    /// Table of contents; pandoc only emits it on standalone pages
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub toc: Option<bool>,
+    /// Defaults to `mathjax`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub math: Option<MathMode>,
+    /// Bibliography files for `[@key]` citations, resolved by citeproc
+    #[serde(
+        default,
+        deserialize_with = "one_or_many",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub bibliography: Option<Vec<PathBuf>>,
+    /// Citation style
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub csl: Option<PathBuf>,
+}
+
+/// Accepts a single path where a list is expected, as pandoc does for `bibliography`
+pub fn one_or_many<'de, D: Deserializer<'de>>(
+    d: D,
+) -> std::result::Result<Option<Vec<PathBuf>>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(PathBuf),
+        Many(Vec<PathBuf>),
+    }
+    Ok(Option::<OneOrMany>::deserialize(d)?.map(|x| match x {
+        OneOrMany::One(p) => vec![p],
+        OneOrMany::Many(v) => v,
+    }))
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MathMode {
+    #[default]
+    Mathjax,
+    Mathml,
+    Katex,
+    Webtex,
+}
+
+impl PandocConfig {
+    fn is_empty(&self) -> bool {
+        self == &PandocConfig::default()
+    }
+
+    /// `self` with every setting `over` has replaced by it
+    pub fn merge(&self, over: &PandocConfig) -> PandocConfig {
+        PandocConfig {
+            from: over.from.clone().or_else(|| self.from.clone()),
+            args: over.args.clone().or_else(|| self.args.clone()),
+            lua_filters: over
+                .lua_filters
+                .clone()
+                .or_else(|| self.lua_filters.clone()),
+            template: over.template.clone().or_else(|| self.template.clone()),
+            highlight_style: over
+                .highlight_style
+                .clone()
+                .or_else(|| self.highlight_style.clone()),
+            toc: over.toc.or(self.toc),
+            math: over.math.or(self.math),
+            bibliography: over
+                .bibliography
+                .clone()
+                .or_else(|| self.bibliography.clone()),
+            csl: over.csl.clone().or_else(|| self.csl.clone()),
+        }
+    }
+
+    /// Paths made relative to the working directory rather than `site_dir`
+    pub fn resolve(mut self, site_dir: &Path) -> PandocConfig {
+        self.lua_filters = self
+            .lua_filters
+            .map(|x| x.iter().map(|p| site_dir.join(p)).collect());
+        self.template = self.template.map(|p| site_dir.join(p));
+        self.bibliography = self
+            .bibliography
+            .map(|x| x.iter().map(|p| site_dir.join(p)).collect());
+        self.csl = self.csl.map(|p| site_dir.join(p));
+        self
+    }
+}
+
+/// Server-side redirect lists written next to the meta-refresh stubs
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct RedirectFiles {
+    /// Netlify-style `_redirects`
+    #[serde(default)]
+    pub netlify: bool,
+    /// `redirects.map` for an nginx `map $uri ...` block
+    #[serde(default)]
+    pub nginx: bool,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct CollectionConfig {
+    pub dir: PathBuf,
+    /// Component wrapping each rendered post; pandoc's standalone page is used when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub layout: Option<PathBuf>,
+    #[serde(default = "default_true")]
+    pub feed: bool,
+    /// Output URL pattern, e.g. `/blog/{year}/{month}/{slug}/`; mirrors the source path when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub permalink: Option<String>,
+}
+
+impl Config {
+    /// `base_path` with a leading and no trailing slash, or empty when served from the root
+    pub fn base_path(&self) -> String {
+        let trimmed = self.base_path.trim_matches('/');
+        if trimmed.is_empty() {
+            String::new()
+        } else {
+            format!("/{trimmed}")
+        }
+    }
+
+    /// Absolute URL of the site root, without trailing slash
+    pub fn site_url(&self) -> String {
+        self.hosted_url.trim_end_matches('/').to_owned() + &self.base_path()
+    }
+
+    pub fn keep_patterns(&self) -> Vec<Pattern> {
+        compile_globs(&self.keep)
+    }
+
+    /// Configured collections, or a single `posts` collection over `posts_dir`
+    pub fn collections(&self) -> BTreeMap<String, CollectionConfig> {
+        let mut res = if self.collections.is_empty() {
+            BTreeMap::from([(
+                DEFAULT_COLLECTION.to_owned(),
+                CollectionConfig {
+                    dir: self.posts_dir.clone(),
+                    layout: None,
+                    feed: true,
+                    permalink: None,
+                },
+            )])
+        } else {
+            self.collections.clone()
+        };
+        for cc in res.values_mut() {
+            if cc.permalink.is_none() {
+                cc.permalink = self.permalink.clone();
+            }
+        }
+        res
+    }
+}
+
+/// Patterns that don't parse are rejected by `read_config`
+fn compile_globs(globs: &[String]) -> Vec<Pattern> {
+    globs.iter().filter_map(|x| Pattern::new(x).ok()).collect()
+}
+
+/// Whether the relative `path` matches any of `patterns`; `*` doesn't cross directories
+pub fn glob_matches(patterns: &[Pattern], path: &Path) -> bool {
+    let opts = MatchOptions {
+        require_literal_separator: true,
+        ..MatchOptions::new()
+    };
+    patterns.iter().any(|x| x.matches_path_with(path, opts))
+}
+
+fn default_posts_dir() -> PathBuf {
+    PathBuf::from("src/posts")
+}
+
+fn default_words_per_minute() -> usize {
+    200
+}
+
+fn default_cjk_chars_per_minute() -> usize {
+    500
+}
+
+fn default_related_count() -> usize {
+    3
+}
+
+fn default_toc_depth() -> usize {
+    3
+}
+
+fn default_min_headings() -> usize {
+    2
+}
+
+fn default_theme() -> String {
+    "InspiredGitHub".to_owned()
+}
+
+fn default_highlight_css() -> PathBuf {
+    PathBuf::from("highlight.css")
+}
+
+fn default_true() -> bool {
+    true
 }
 
 impl Default for Config {
@@ -22,11 +351,31 @@ impl Default for Config {
         Config {
             styles_css: PathBuf::from("src/styles.css"),
             components_dir: PathBuf::from("src/components"),
-            posts_dir: PathBuf::from("src/posts"),
+            posts_dir: default_posts_dir(),
             hosted_url: "https://example.com".to_owned(),
             og_image_url: "https://upload.wikimedia.org/wikipedia/en/a/a9/Example.jpg".to_owned(),
             site_name: "My Site".to_owned(),
             description: "My lovely website".to_owned(),
+            collections: BTreeMap::new(),
+            permalink: None,
+            redirects: BTreeMap::new(),
+            redirect_files: RedirectFiles::default(),
+            base_path: String::new(),
+            keep: vec![],
+            ignore: vec![],
+            static_dir: None,
+            // New sites render math at build time and ship no JavaScript
+            pandoc: PandocConfig {
+                math: Some(MathMode::Mathml),
+                ..Default::default()
+            },
+            toc: TocConfig::default(),
+            series_layout: Some(PathBuf::from("series.html")),
+            words_per_minute: default_words_per_minute(),
+            cjk_chars_per_minute: default_cjk_chars_per_minute(),
+            highlight: None,
+            related: None,
+            backlinks: None,
         }
     }
 }
@@ -39,6 +388,18 @@ pub enum ConfigError {
     CannotFindConfig { source: io::Error },
     #[error("Missing fields {source}")]
     MissingFields { source: serde_json::Error },
+    #[error("Unknown highlight theme '{theme}', expected one of: {}", available.join(", "))]
+    UnknownTheme {
+        theme: String,
+        available: Vec<&'static str>,
+    },
+    #[error("Invalid glob pattern '{pattern}': {source}")]
+    InvalidGlob {
+        pattern: String,
+        source: glob::PatternError,
+    },
+    #[error("Collection '{name}' has dir '{}', but collection dirs must be inside src/", dir.display())]
+    CollectionOutsideSrc { name: String, dir: PathBuf },
 }
 
 pub fn read_config(path: &Path) -> Result<Config> {
@@ -46,5 +407,31 @@ pub fn read_config(path: &Path) -> Result<Config> {
         &read_to_string(path).map_err(|e| ConfigError::CannotFindConfig { source: e })?,
     )
     .map_err(|e| ConfigError::MissingFields { source: e })?;
+    for pattern in &res.keep {
+        Pattern::new(pattern).map_err(|e| ConfigError::InvalidGlob {
+            pattern: pattern.clone(),
+            source: e,
+        })?;
+    }
+    if let Some(hc) = &res.highlight
+        && highlight::theme(&hc.theme).is_none()
+    {
+        return Err(ConfigError::UnknownTheme {
+            theme: hc.theme.clone(),
+            available: highlight::theme_names(),
+        });
+    }
+    for pattern in &res.ignore {
+        ignore::validate(pattern).map_err(|e| ConfigError::InvalidGlob {
+            pattern: pattern.clone(),
+            source: e,
+        })?;
+    }
+    // Only src/ is rendered; posts elsewhere would be listed but never built
+    for (name, cc) in res.collections() {
+        if !normalize(&cc.dir).starts_with("src") {
+            return Err(ConfigError::CollectionOutsideSrc { name, dir: cc.dir });
+        }
+    }
     Ok(res)
 }
@@ -4,7 +4,7 @@ use std::{
     path::Path,
 };
 
-use crate::{config::Config, markdown::create_post};
+use crate::{config::Config, ignore::IGNORE_FILE, markdown::create_post};
 
 pub fn create_project(dir: impl AsRef<Path>) -> Result<(), Box<dyn Error>> {
     let dir = dir.as_ref();
@@ -24,6 +24,8 @@ pub fn create_project(dir: impl AsRef<Path>) -> Result<(), Box<dyn Error>> {
         serde_json::to_string(&Config::default()).unwrap(),
     )?;
 
+    write(dir.join(IGNORE_FILE), DEFAULT_IGNORE)?;
+
     let src_path = dir.join("src");
     create_dir(&src_path)?;
 
@@ -36,6 +38,7 @@ pub fn create_project(dir: impl AsRef<Path>) -> Result<(), Box<dyn Error>> {
     write(components_path.join("header.html"), DEFAULT_HEADER)?;
     write(components_path.join("footer.html"), DEFAULT_FOOTER)?;
     write(components_path.join("post.html"), DEFAULT_FEED_POST)?;
+    write(components_path.join("series.html"), DEFAULT_SERIES)?;
 
     let posts_path = src_path.join("posts");
     create_dir(&posts_path)?;
@@ -62,10 +65,30 @@ const DEFAULT_INDEX: &str = r#"<!doctype html>
 </html>
 "#;
 
+const DEFAULT_IGNORE: &str = "# Sources the build skips, see readme\n.DS_Store\n*.swp\n*~\n";
+
 const DEFAULT_CSS: &str = "/* Add your styles */\n";
 
-const DEFAULT_FEED: &str = "<FEED with=\"post.html\">\n";
+const DEFAULT_FEED: &str = "<FEED with=\"post.html\" />\n";
 
 const DEFAULT_HEADER: &str = "<div>header</div>\n";
 const DEFAULT_FOOTER: &str = "<div>footer</div>\n";
 const DEFAULT_FEED_POST: &str = "wip\n";
+
+const DEFAULT_SERIES: &str = r#"<!doctype html>
+<html lang="en">
+  <head>
+    <meta charset="utf-8" />
+    <title>{TITLE}</title>
+    <link rel="stylesheet" href="{STYLES}" />
+  </head>
+  <body>
+    <REPLACE with="header.html" />
+
+    <h1>{TITLE}</h1>
+    {CONTENT}
+
+    <REPLACE with="footer.html" />
+  </body>
+</html>
+"#;
@@ -0,0 +1,109 @@
+use std::{
+    collections::HashSet,
+    fmt,
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
+};
+
+/// A message pointing at a span of a source file, printed like rustc does
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub level: Level,
+    pub message: String,
+    pub path: PathBuf,
+    /// 1-based
+    pub line: usize,
+    /// 1-based, in characters
+    pub column: usize,
+    pub source_line: String,
+    /// Characters to underline, at least 1
+    pub width: usize,
+    pub hint: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Error,
+    Warning,
+}
+
+impl Diagnostic {
+    /// Diagnostic for the `len` bytes at byte `offset` of `contents`
+    pub fn at(
+        level: Level,
+        message: impl Into<String>,
+        path: &Path,
+        contents: &str,
+        offset: usize,
+        len: usize,
+    ) -> Self {
+        let offset = offset.min(contents.len());
+        let line_start = contents[..offset].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = contents[offset..]
+            .find('\n')
+            .map_or(contents.len(), |i| offset + i);
+        let source_line = contents[line_start..line_end].trim_end_matches('\r');
+        let span_end = (offset + len).min(line_end);
+
+        Diagnostic {
+            level,
+            message: message.into(),
+            path: path.to_path_buf(),
+            line: contents[..line_start].matches('\n').count() + 1,
+            column: contents[line_start..offset].chars().count() + 1,
+            source_line: source_line.to_owned(),
+            width: contents[offset..span_end].chars().count().max(1),
+            hint: None,
+        }
+    }
+
+    pub fn with_hint(mut self, hint: impl Into<String>) -> Self {
+        self.hint = Some(hint.into());
+        self
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let level = match self.level {
+            Level::Error => "error",
+            Level::Warning => "warning",
+        };
+        let gutter = " ".repeat(self.line.to_string().len());
+        writeln!(f, "{level}: {}", self.message)?;
+        writeln!(
+            f,
+            "{gutter}--> {}:{}:{}",
+            self.path.display(),
+            self.line,
+            self.column
+        )?;
+        writeln!(f, "{gutter} |")?;
+        writeln!(f, "{} | {}", self.line, self.source_line)?;
+        write!(
+            f,
+            "{gutter} | {}{}",
+            " ".repeat(self.column - 1),
+            "^".repeat(self.width)
+        )?;
+        if let Some(hint) = &self.hint {
+            write!(f, "\n{gutter} = help: {hint}")?;
+        }
+        Ok(())
+    }
+}
+
+// Warnings already printed, so a component hydrated once per post only warns once
+static WARNED: OnceLock<Mutex<HashSet<(PathBuf, usize, usize)>>> = OnceLock::new();
+
+/// Prints a warning to stderr unless the same location was already warned about
+pub fn warn(d: Diagnostic) {
+    let warned = WARNED.get_or_init(|| Mutex::new(HashSet::new()));
+    if warned
+        .lock()
+        .unwrap()
+        .insert((d.path.clone(), d.line, d.column))
+    {
+        eprintln!("{d}\n");
+    }
+}
@@ -0,0 +1,244 @@
+use std::{
+    collections::BTreeSet,
+    fmt::Write,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use pathdiff::diff_paths;
+use regex::{Captures, Regex};
+use syntect::{
+    highlighting::{Theme, ThemeSet},
+    html::{ClassStyle, css_for_theme_with_class_style, line_tokens_to_classed_spans},
+    parsing::{ParseState, ScopeStack, SyntaxReference, SyntaxSet},
+};
+
+use crate::config::HighlightConfig;
+
+const CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed { prefix: "hl-" };
+
+fn syntaxes() -> &'static SyntaxSet {
+    static SYNTAXES: OnceLock<SyntaxSet> = OnceLock::new();
+    SYNTAXES.get_or_init(SyntaxSet::load_defaults_nonewlines)
+}
+
+fn themes() -> &'static ThemeSet {
+    static THEMES: OnceLock<ThemeSet> = OnceLock::new();
+    THEMES.get_or_init(ThemeSet::load_defaults)
+}
+
+pub fn theme(name: &str) -> Option<&'static Theme> {
+    themes().themes.get(name)
+}
+
+/// Names accepted by `highlight.theme`
+pub fn theme_names() -> Vec<&'static str> {
+    themes().themes.keys().map(String::as_str).collect()
+}
+
+/// Rewrites ```` ```rust {3-5} ```` fences into pandoc attributes (```` ```{.rust data-hl="3-5"} ````)
+pub fn rewrite_fences(source: &str) -> String {
+    let re =
+        Regex::new(r"(?m)^([ \t]*(?:`{3,}|~{3,}))[ \t]*([\w+#.-]+)[ \t]*\{([\d,\s-]+)\}[ \t]*$")
+            .unwrap();
+    re.replace_all(source, |caps: &Captures| {
+        let ranges = caps[3].split_whitespace().collect::<String>();
+        format!(r#"{}{{.{} data-hl="{ranges}"}}"#, &caps[1], &caps[2])
+    })
+    .into_owned()
+}
+
+/// Highlights every `<pre><code>` block of `html` whose language is known
+///
+/// The language comes from a `language-x` class on either element, or a plain class on `<pre>` as
+/// pandoc writes it. Lines listed in a `data-hl="3-5,8"` attribute are marked; a `numberLines` or
+/// `line-numbers` class, or `line_numbers` in the config, numbers the lines. Blocks that already
+/// contain markup are left alone.
+pub fn highlight_html(html: &str, c: &HighlightConfig) -> String {
+    let block = Regex::new(r"(?s)<pre\b([^>]*)>\s*<code\b([^>]*)>(.*?)</code>\s*</pre>").unwrap();
+    let class = Regex::new(r#"\bclass="([^"]*)""#).unwrap();
+    let marked = Regex::new(r#"\bdata-hl="([^"]*)""#).unwrap();
+
+    block
+        .replace_all(html, |caps: &Captures| {
+            let attrs = format!("{} {}", &caps[1], &caps[2]);
+            let classes = class
+                .captures_iter(&attrs)
+                .flat_map(|x| {
+                    x[1].split_whitespace()
+                        .map(str::to_owned)
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>();
+            let syntax = classes.iter().find_map(|x| {
+                let lang = x.strip_prefix("language-").unwrap_or(x);
+                syntaxes().find_syntax_by_token(lang)
+            });
+            let (Some(syntax), false) = (syntax, caps[3].contains('<')) else {
+                return caps[0].to_owned();
+            };
+            let numbered = c.line_numbers
+                || classes
+                    .iter()
+                    .any(|x| x == "numberLines" || x == "line-numbers");
+            let marked = marked
+                .captures(&attrs)
+                .map(|x| parse_ranges(&x[1]))
+                .unwrap_or_default();
+            highlight_block(&unescape(&caps[3]), syntax, numbered, &marked)
+                .unwrap_or_else(|| caps[0].to_owned())
+        })
+        .into_owned()
+}
+
+/// One `<span class="hl-line">` per line, each closing the scopes it opened
+fn highlight_block(
+    code: &str,
+    syntax: &SyntaxReference,
+    numbered: bool,
+    marked: &BTreeSet<usize>,
+) -> Option<String> {
+    let mut state = ParseState::new(syntax);
+    let mut stack = ScopeStack::new();
+    let mut res = String::from(r#"<pre class="hl-code"#);
+    if numbered {
+        res.push_str(" hl-numbered");
+    }
+    res.push_str(r#""><code>"#);
+
+    for (i, line) in code.strip_suffix('\n').unwrap_or(code).lines().enumerate() {
+        let ops = state.parse_line(line, syntaxes()).ok()?;
+        let class = if marked.contains(&(i + 1)) {
+            "hl-line hl-marked"
+        } else {
+            "hl-line"
+        };
+        write!(res, r#"<span class="{class}">"#).ok()?;
+        // Reopen the scopes still open from the previous line
+        for scope in stack.as_slice() {
+            let classes = scope
+                .build_string()
+                .split('.')
+                .map(|x| format!("hl-{x}"))
+                .collect::<Vec<_>>()
+                .join(" ");
+            write!(res, r#"<span class="{classes}">"#).ok()?;
+        }
+        let (spans, _) = line_tokens_to_classed_spans(line, &ops, CLASS_STYLE, &mut stack).ok()?;
+        res.push_str(&spans);
+        res.push_str(&"</span>".repeat(stack.len()));
+        res.push_str("\n</span>");
+    }
+    res.push_str("</code></pre>");
+    Some(res)
+}
+
+/// `3-5,8` -> {3, 4, 5, 8}; malformed parts are skipped
+fn parse_ranges(ranges: &str) -> BTreeSet<usize> {
+    ranges
+        .split(',')
+        .filter_map(|part| {
+            let (start, end) = part.split_once('-').unwrap_or((part, part));
+            Some(start.trim().parse().ok()?..=end.trim().parse().ok()?)
+        })
+        .flatten()
+        .collect()
+}
+
+fn unescape(code: &str) -> String {
+    code.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&#x27;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Theme colours plus the rules for line numbers and marked lines
+pub fn stylesheet(c: &HighlightConfig) -> String {
+    // `read_config` rejects unknown themes
+    let theme = theme(&c.theme).unwrap();
+    let mut css = css_for_theme_with_class_style(theme, CLASS_STYLE).unwrap_or_default();
+    let marked = theme
+        .settings
+        .line_highlight
+        .map_or("rgba(255, 255, 0, 0.15)".to_owned(), |x| {
+            format!("#{:02x}{:02x}{:02x}", x.r, x.g, x.b)
+        });
+    write!(
+        css,
+        r#"
+.hl-line {{ display: block; }}
+.hl-marked {{ background-color: {marked}; }}
+.hl-numbered {{ counter-reset: hl-line; }}
+.hl-numbered .hl-line::before {{
+ counter-increment: hl-line;
+ content: counter(hl-line);
+ display: inline-block;
+ width: 2.5em;
+ margin-right: 1em;
+ text-align: right;
+ opacity: 0.5;
+ user-select: none;
+}}
+"#
+    )
+    .unwrap();
+    css
+}
+
+/// Links the stylesheet at `stylesheet` (in the output dir) from `html` written to `dest`,
+/// if `html` has highlighted code and a `<head>` that doesn't link it yet
+pub fn add_stylesheet_link(html: &str, dest: &Path, stylesheet: &Path) -> String {
+    if !html.contains(r#"class="hl-code"#) {
+        return html.to_owned();
+    }
+    let href =
+        diff_paths(stylesheet, dest.parent().unwrap()).unwrap_or_else(|| PathBuf::from(stylesheet));
+    let href = href.to_string_lossy();
+    if html.contains(&format!(r#"href="{href}""#)) {
+        return html.to_owned();
+    }
+    html.replacen(
+        "</head>",
+        &format!("<link rel=\"stylesheet\" href=\"{href}\" />\n</head>"),
+        1,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranges(x: &str) -> Vec<usize> {
+        parse_ranges(x).into_iter().collect()
+    }
+
+    #[test]
+    fn ranges_and_single_lines() {
+        assert_eq!(ranges("3-5,8"), [3, 4, 5, 8]);
+        assert_eq!(ranges(" 2 , 4 - 5 "), [2, 4, 5]);
+        assert_eq!(ranges("5,1-2,2"), [1, 2, 5]);
+    }
+
+    #[test]
+    fn malformed_parts_are_skipped() {
+        assert_eq!(ranges("x,2,-,3-y"), [2]);
+        assert_eq!(ranges("5-3"), Vec::<usize>::new());
+        assert_eq!(ranges(""), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn fences_with_ranges_become_pandoc_attributes() {
+        assert_eq!(
+            rewrite_fences("```rust {3-5, 8}\nfn main() {}\n```\n"),
+            "```{.rust data-hl=\"3-5,8\"}\nfn main() {}\n```\n"
+        );
+        assert_eq!(rewrite_fences("```rust\n```\n"), "```rust\n```\n");
+    }
+
+    #[test]
+    fn unescape_decodes_ampersand_last() {
+        assert_eq!(unescape("&amp;lt; &lt;a&gt;"), "&lt; <a>");
+    }
+}
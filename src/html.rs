@@ -1,26 +1,37 @@
-use pathdiff::diff_paths;
 use regex::Regex;
 use std::{
     collections::HashMap,
     fmt::Debug,
-    fs::{read_to_string, write},
+    fs::read_to_string,
     path::{Path, PathBuf},
     sync::{Mutex, OnceLock},
 };
 
 use crate::{
-    config::Config,
-    markdown::{MdError, get_mdinfos_for_path, truncate_content},
+    collection::{self, Collection},
+    config::{Config, DEFAULT_COLLECTION},
+    diagnostic::{self, Diagnostic, Level},
+    markdown::{MdError, MdInfo, truncate_content},
+    permalink::relative_link,
     rss::add_rss_meta,
 };
 
 #[derive(Debug, thiserror::Error)]
 pub enum HtmlError {
-    #[error("I/O reading component {path}: {source}")]
+    #[error("I/O on {path}: {source}")]
     Io { path: PathBuf, #[source] source: std::io::Error },
 
-    #[error("Missing field '{tag}' in component template")]
-    MissingField { tag: String },
+    #[error("{0}")]
+    MissingField(Box<Diagnostic>),
+
+    #[error("{0}")]
+    InvalidTag(Box<Diagnostic>),
+
+    #[error("Unknown collection '{name}'")]
+    UnknownCollection { name: String },
+
+    #[error("`<FEED>` without `collection=` needs a '{DEFAULT_COLLECTION}' collection or a single one, but there are: {}", available.join(", "))]
+    NoDefaultCollection { available: Vec<String> },
 
     #[error(transparent)]
     Markdown(#[from] MdError),
@@ -49,18 +60,95 @@ fn get_component(path: &Path) -> Result<String> {
 pub fn generate_substituted_html(
     src: &Path,
     dest: &Path,
-    posts_dir: &Path,
+    build_dir: &Path,
+    collections: &[Collection],
     components_dir: &Path,
     config: &Config,
-) -> Result<()> {
-    let mut contents = read_to_string(src).unwrap();
+) -> Result<String> {
+    let mut contents = read_to_string(src).map_err(|e| HtmlError::Io {
+        path: src.into(),
+        source: e,
+    })?;
+    check_tags(src, &contents)?;
     contents = substitute_replace(&contents, components_dir)?;
-    contents = substitute_feed(&contents, src, components_dir, posts_dir)?;
-    contents = add_rss_meta(&contents, &config.hosted_url, &config.site_name);
-    write(dest, contents).unwrap();
+    contents = substitute_feed(
+        src,
+        &contents,
+        dest,
+        build_dir,
+        components_dir,
+        collections,
+        config,
+    )?;
+    contents = add_rss_meta(&contents, collections, config);
+    Ok(add_base_path(&contents, &config.base_path()))
+}
+
+/// Expands `<REPLACE>` tags in a layout component, then fills in its placeholders
+pub fn render_layout(
+    layout: &Path,
+    components_dir: &Path,
+    fields: HashMap<&str, String>,
+) -> Result<String> {
+    let path = components_dir.join(layout);
+    let layout = get_component(&path)?;
+    check_tags(&path, &layout)?;
+    warn_unknown_placeholders(&path, &layout, &fields);
+    let layout = substitute_replace(&layout, components_dir)?;
+    Ok(hydrate_component(&layout, fields))
+}
+
+/// Rejects `<REPLACE>`/`<FEED>` tags without a `with` attribute; warns about ones that aren't
+/// self-closing, which are left in the output untouched
+fn check_tags(path: &Path, contents: &str) -> Result<()> {
+    let re = Regex::new(r#"<(REPLACE|FEED)\b([^>]*)>"#).unwrap();
+    for caps in re.captures_iter(contents) {
+        let m = caps.get(0).unwrap();
+        let d = |level, message| {
+            Diagnostic::at(level, message, path, contents, m.start(), m.len()).with_hint(format!(
+                r#"write it as `<{} with="component.html" />`"#,
+                &caps[1]
+            ))
+        };
+        if !caps[2].trim_end().ends_with('/') {
+            diagnostic::warn(d(
+                Level::Warning,
+                format!("`<{}>` isn't self-closing and is left as is", &caps[1]),
+            ));
+        } else if !parse_attrs(&caps[2]).contains_key("with") {
+            let message = format!("`<{}>` is missing its `with` attribute", &caps[1]);
+            return Err(HtmlError::InvalidTag(Box::new(d(Level::Error, message))));
+        }
+    }
     Ok(())
 }
 
+/// Warns about `{PLACEHOLDER}`s in a component that nothing will fill in
+fn warn_unknown_placeholders(path: &Path, template: &str, fields: &HashMap<&str, String>) {
+    let re = Regex::new(r"\{([A-Z][A-Z_]*)\}").unwrap();
+    for caps in re.captures_iter(template) {
+        if !fields.contains_key(&caps[1]) {
+            let m = caps.get(0).unwrap();
+            let mut known = fields
+                .keys()
+                .map(|k| format!("{{{k}}}"))
+                .collect::<Vec<_>>();
+            known.sort();
+            diagnostic::warn(
+                Diagnostic::at(
+                    Level::Warning,
+                    format!("unknown placeholder `{}` is left as is", m.as_str()),
+                    path,
+                    template,
+                    m.start(),
+                    m.len(),
+                )
+                .with_hint(format!("available here: {}", known.join(", "))),
+            );
+        }
+    }
+}
+
 pub fn substitute_replace(contents: &str, components_dir: &Path) -> Result<String> {
     let re = Regex::new(r#"<REPLACE\b[^>]*\bwith="([^"]*)"[^>]*/>"#).unwrap();
 
@@ -71,13 +159,9 @@ pub fn substitute_replace(contents: &str, components_dir: &Path) -> Result<Strin
         let whole = caps.get(0).unwrap();
         out.push_str(&contents[last_end..whole.start()]);
 
-        let with = caps
-            .get(1)
-            .ok_or_else(|| HtmlError::MissingField { tag: "with".into() })?
-            .as_str();
-
-        let path = components_dir.join(with);
+        let path = components_dir.join(&caps[1]);
         let component = get_component(&path)?;
+        check_tags(&path, &component)?;
 
         out.push_str(&component);
         last_end = whole.end();
@@ -87,20 +171,18 @@ pub fn substitute_replace(contents: &str, components_dir: &Path) -> Result<Strin
     Ok(out)
 }
 
+/// Expands `<FEED>` tags in `contents`, `src` after its `<REPLACE>` tags were expanded
 fn substitute_feed(
+    src: &Path,
     contents: &str,
-    curr_path: &Path,
+    dest: &Path,
+    build_dir: &Path,
     components_dir: &Path,
-    posts_dir: &Path,
+    collections: &[Collection],
+    c: &Config,
 ) -> Result<String> {
-    // Precompute data that is the same for every match
-    let mut mdinfos =
-        get_mdinfos_for_path(posts_dir)?; // was unwrap
-    mdinfos.sort();
-    mdinfos.reverse();
-
     // We will walk matches and splice replacements
-    let re = Regex::new(r#"<FEED\b[^>]*\bwith="([^"]*)"[^>]*/>"#).expect("Regex fail how"); // hard-coded? make it static and expect() instead
+    let re = Regex::new(r#"<FEED\b([^>]*)/>"#).expect("Regex fail how"); // hard-coded? make it static and expect() instead
     let mut out = String::with_capacity(contents.len());
     let mut last_end = 0;
 
@@ -108,34 +190,34 @@ fn substitute_feed(
         let m = caps.get(0).unwrap(); // whole match span
         out.push_str(&contents[last_end..m.start()]);
 
-        let with = caps
-            .get(1)
-            .ok_or_else(|| HtmlError::MissingField { tag: "with".into() })?;
-        // .ok_or_else(|| anyhow::anyhow!("expected capture group 1"))?
-        // .as_str();
-        //
-        let component_path = components_dir.join(with.as_str());
+        let attrs = parse_attrs(&caps[1]);
+        let with = attrs.get("with").ok_or_else(|| {
+            let d = Diagnostic::at(
+                Level::Error,
+                "`<FEED>` is missing its `with` attribute",
+                src,
+                contents,
+                m.start(),
+                m.len(),
+            )
+            .with_hint(r#"write it as `<FEED with="component.html" />`"#);
+            HtmlError::MissingField(Box::new(d))
+        })?;
+        let mdinfos = &match attrs.get("collection") {
+            Some(name) => collection::find(collections, name)
+                .ok_or_else(|| HtmlError::UnknownCollection { name: name.into() })?,
+            None => default_collection(collections)?,
+        }
+        .posts;
+
+        let component_path = components_dir.join(with);
         let component_tpl = get_component(&component_path)?; // now ? works
+        check_tags(&component_path, &component_tpl)?;
 
-        // Build hydrated components once per match (if it really varies by `with`)
         let hydrated = mdinfos
             .iter()
-            .map(|c| {
-                let mut new_path = c.path.clone();
-                new_path.set_extension("html");
-                let rel = diff_paths(&new_path, curr_path.parent().unwrap())
-                    .unwrap()
-                    .to_string_lossy()
-                    .to_string();
-                let map = HashMap::from([
-                    ("TITLE", c.title.clone()),
-                    ("DATE", c.date.format("%A %d %B %Y").to_string()),
-                    ("CONTENT", truncate_content(&c.content, 160)),
-                    ("PATH", rel),
-                ]);
-                hydrate_component(&component_tpl, map)
-            })
-            .collect::<Vec<String>>(); // if hydrate_component returns Result<String>
+            .map(|info| render_item(&component_path, &component_tpl, info, dest, build_dir, c))
+            .collect::<Vec<String>>();
 
         out.push_str(&hydrated.join("\n"));
 
@@ -146,12 +228,128 @@ fn substitute_feed(
     Ok(out)
 }
 
+/// The `posts` collection, or the only one there is
+fn default_collection(collections: &[Collection]) -> Result<&Collection> {
+    match (
+        collection::find(collections, DEFAULT_COLLECTION),
+        collections,
+    ) {
+        (Some(x), _) | (None, [x]) => Ok(x),
+        (None, _) => Err(HtmlError::NoDefaultCollection {
+            available: collections.iter().map(|x| x.name.clone()).collect(),
+        }),
+    }
+}
+
+/// `posts` through the item component `with`, one after another
+pub fn render_items<'a>(
+    with: &Path,
+    components_dir: &Path,
+    posts: impl IntoIterator<Item = &'a MdInfo>,
+    dest: &Path,
+    build_dir: &Path,
+    c: &Config,
+) -> Result<String> {
+    let path = components_dir.join(with);
+    let template = get_component(&path)?;
+    check_tags(&path, &template)?;
+    Ok(posts
+        .into_iter()
+        .map(|info| render_item(&path, &template, info, dest, build_dir, c))
+        .collect::<Vec<_>>()
+        .join("\n"))
+}
+
+/// A post as listed by `<FEED>`, `{RELATED}` and `{BACKLINKS}`, through the item component `template`
+pub fn render_item(
+    path: &Path,
+    template: &str,
+    info: &MdInfo,
+    dest: &Path,
+    build_dir: &Path,
+    c: &Config,
+) -> String {
+    let map = HashMap::from([
+        ("TITLE", info.title.clone()),
+        ("DATE", info.date.format("%A %d %B %Y").to_string()),
+        ("CONTENT", truncate_content(&info.content, 160)),
+        ("PATH", relative_link(dest, build_dir, &info.url)),
+        ("WORDS", info.words.to_string()),
+        ("READING_TIME", info.reading_minutes(c).to_string()),
+    ]);
+    warn_unknown_placeholders(path, template, &map);
+    hydrate_component(template, map)
+}
+
+/// Prefixes root-relative `href`/`src` attributes with the site's base path
+pub fn add_base_path(contents: &str, base_path: &str) -> String {
+    if base_path.is_empty() {
+        return contents.to_owned();
+    }
+    let re = Regex::new(r#"\b(href|src)=(?:"(/[^"]*)"|'(/[^']*)')"#).unwrap();
+
+    re.replace_all(contents, |caps: &regex::Captures| {
+        let url = caps.get(2).or_else(|| caps.get(3)).unwrap().as_str();
+        let prefixed = url == base_path || url.starts_with(&format!("{base_path}/"));
+        if url.starts_with("//") || prefixed {
+            caps[0].to_owned()
+        } else {
+            format!(r#"{}="{base_path}{url}""#, &caps[1])
+        }
+    })
+    .to_string()
+}
+
+/// `key="value"` pairs of a tag's attribute list
+fn parse_attrs(attrs: &str) -> HashMap<String, String> {
+    let re = Regex::new(r#"\b([[:alpha:]]+)="([^"]*)""#).unwrap();
+    re.captures_iter(attrs)
+        .map(|caps| (caps[1].to_owned(), caps[2].to_owned()))
+        .collect()
+}
+
 fn hydrate_component(component: &str, fields: HashMap<&str, String>) -> String {
-    let re = Regex::new(r"\{([[:alpha:]]*)\}").unwrap();
+    let re = Regex::new(r"\{([[:alpha:]_]*)\}").unwrap();
 
-    re.replace_all(&component, |caps: &regex::Captures| {
+    re.replace_all(component, |caps: &regex::Captures| {
         let key = &caps[1]; // capture group 1
         fields.get(key).map(|s| s.as_str()).unwrap_or(&caps[0]).to_owned() // original "{KEY}"
     })
     .to_string()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base_path_prefixes_root_relative_urls() {
+        assert_eq!(
+            add_base_path(
+                r#"<a href="/blog/">b</a><a href="c.html">c</a>"#,
+                "/project"
+            ),
+            r#"<a href="/project/blog/">b</a><a href="c.html">c</a>"#
+        );
+        assert_eq!(
+            add_base_path("<img src='/pic.png'>", "/project"),
+            r#"<img src="/project/pic.png">"#
+        );
+    }
+
+    #[test]
+    fn base_path_skips_protocol_relative_and_prefixed_urls() {
+        let html = r#"<script src="//cdn.example.com/x.js"></script><a href="/project/a/">a</a><a href="/project">p</a>"#;
+        assert_eq!(add_base_path(html, "/project"), html);
+        assert_eq!(
+            add_base_path(r#"<a href="/projects/">a</a>"#, "/project"),
+            r#"<a href="/project/projects/">a</a>"#
+        );
+    }
+
+    #[test]
+    fn empty_base_path_changes_nothing() {
+        let html = r#"<a href="/blog/">b</a><img src='/pic.png'>"#;
+        assert_eq!(add_base_path(html, ""), html);
+    }
+}
@@ -0,0 +1,163 @@
+use std::{
+    fs::read_to_string,
+    path::{Path, PathBuf},
+};
+
+use glob::{MatchOptions, Pattern};
+use pathdiff::diff_paths;
+
+use crate::{
+    config::Config,
+    diagnostic::{Diagnostic, Level, warn},
+};
+
+/// File in the site root listing glob patterns of sources to skip, one per line
+pub const IGNORE_FILE: &str = ".ssgignore";
+
+/// Sources excluded from the build by `.ssgignore` and the config's `ignore` list
+///
+/// Like `.gitignore`, a pattern without a `/` matches a file or directory name at any depth
+/// (`*.swp`, `.DS_Store`, `drafts`); one with a `/` matches the path relative to the site root
+/// (`src/notes/README.md`). Ignoring a directory ignores everything below it.
+pub struct Ignore {
+    root: PathBuf,
+    /// Pattern and whether it is matched against the whole relative path
+    patterns: Vec<(Pattern, bool)>,
+}
+
+impl Ignore {
+    /// Patterns of `.ssgignore` that don't parse are warned about and skipped
+    pub fn new(site_dir: &Path, c: &Config) -> Self {
+        let mut patterns = vec![];
+        for pattern in &c.ignore {
+            // Validated by `read_config`
+            if let Some(x) = parse(pattern) {
+                patterns.push(x);
+            }
+        }
+
+        let path = site_dir.join(IGNORE_FILE);
+        let contents = read_to_string(&path).unwrap_or_default();
+        let mut offset = 0;
+        for line in contents.split_inclusive('\n') {
+            let pattern = line.trim();
+            if !pattern.is_empty() && !pattern.starts_with('#') {
+                match parse(pattern) {
+                    Some(x) => patterns.push(x),
+                    None => warn(Diagnostic::at(
+                        Level::Warning,
+                        format!("invalid glob pattern '{pattern}', ignoring it"),
+                        &path,
+                        &contents,
+                        offset + line.find(pattern).unwrap_or(0),
+                        pattern.len(),
+                    )),
+                }
+            }
+            offset += line.len();
+        }
+
+        Ignore {
+            root: site_dir.to_path_buf(),
+            patterns,
+        }
+    }
+
+    /// Whether `path`, or a directory containing it, matches a pattern
+    pub fn is_ignored(&self, path: &Path) -> bool {
+        let Some(relative) = diff_paths(path, &self.root) else {
+            return false;
+        };
+        let opts = MatchOptions {
+            require_literal_separator: true,
+            ..MatchOptions::new()
+        };
+        relative
+            .ancestors()
+            .filter(|x| !x.as_os_str().is_empty())
+            .any(|x| {
+                self.patterns.iter().any(|(pattern, anchored)| {
+                    if *anchored {
+                        pattern.matches_path_with(x, opts)
+                    } else {
+                        x.file_name()
+                            .is_some_and(|name| pattern.matches_with(&name.to_string_lossy(), opts))
+                    }
+                })
+            })
+    }
+}
+
+/// A leading `/` anchors a pattern to the site root; a trailing `/` is accepted and dropped
+fn parse(pattern: &str) -> Option<(Pattern, bool)> {
+    let trimmed = pattern.trim_end_matches('/');
+    let anchored = trimmed.contains('/');
+    Pattern::new(trimmed.trim_start_matches('/'))
+        .ok()
+        .map(|x| (x, anchored))
+}
+
+/// Checks a pattern of the config's `ignore` list
+pub fn validate(pattern: &str) -> Result<(), glob::PatternError> {
+    Pattern::new(pattern.trim_end_matches('/').trim_start_matches('/')).map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ignore(patterns: &[&str]) -> Ignore {
+        Ignore {
+            root: PathBuf::from("/site"),
+            patterns: patterns.iter().filter_map(|x| parse(x)).collect(),
+        }
+    }
+
+    fn ignored(x: &Ignore, path: &str) -> bool {
+        x.is_ignored(Path::new(path))
+    }
+
+    #[test]
+    fn names_match_at_any_depth() {
+        let x = ignore(&["*.swp", ".DS_Store"]);
+        assert!(ignored(&x, "/site/src/a.md.swp"));
+        assert!(ignored(&x, "/site/src/posts/deep/.DS_Store"));
+        assert!(!ignored(&x, "/site/src/a.md"));
+    }
+
+    #[test]
+    fn ignoring_a_directory_ignores_its_contents() {
+        let x = ignore(&["drafts/"]);
+        assert!(ignored(&x, "/site/src/posts/drafts"));
+        assert!(ignored(&x, "/site/src/posts/drafts/a/b.md"));
+        assert!(!ignored(&x, "/site/src/posts/drafts.md"));
+    }
+
+    #[test]
+    fn patterns_with_a_slash_are_anchored_to_the_root() {
+        let x = ignore(&["src/notes/README.md", "/src/tmp"]);
+        assert!(ignored(&x, "/site/src/notes/README.md"));
+        assert!(!ignored(&x, "/site/src/posts/notes/README.md"));
+        assert!(ignored(&x, "/site/src/tmp/x.html"));
+        assert!(!ignored(&x, "/site/src/posts/tmp/x.html"));
+    }
+
+    #[test]
+    fn star_does_not_cross_directories() {
+        let x = ignore(&["src/*.md"]);
+        assert!(ignored(&x, "/site/src/a.md"));
+        assert!(!ignored(&x, "/site/src/posts/a.md"));
+    }
+
+    #[test]
+    fn paths_outside_the_site_are_not_ignored() {
+        let x = ignore(&["*"]);
+        assert!(!ignored(&x, "relative/a.md"));
+    }
+
+    #[test]
+    fn invalid_patterns_are_rejected() {
+        assert!(validate("src/[").is_err());
+        assert!(validate("/drafts/").is_ok());
+    }
+}
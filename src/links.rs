@@ -0,0 +1,354 @@
+use std::path::{Component, Path, PathBuf};
+
+use pathdiff::diff_paths;
+use regex::{Captures, Regex};
+
+use crate::{
+    collection::Collection,
+    markdown::{MdError, MdInfo, Result},
+    permalink::{mirrored, relative_link},
+};
+
+#[derive(Debug)]
+pub struct BrokenLink {
+    pub line: usize,
+    pub target: String,
+    pub reason: String,
+}
+
+/// Where the build writes things, for resolving links from the post at `dest`
+pub struct LinkContext<'a> {
+    pub src_dir: &'a Path,
+    pub build_dir: &'a Path,
+    pub dest: &'a Path,
+    pub collections: &'a [Collection],
+}
+
+/// Rewrites relative links to other markdown sources and attachments to their output URLs, and
+/// `[[Post Title]]`/`[[slug|text]]` wiki links to markdown links.
+///
+/// `source` is the whole file so reported line numbers match the editor's.
+pub fn rewrite_links(md_path: &Path, source: &str, ctx: &LinkContext) -> Result<String> {
+    let inline = Regex::new(r"\]\(\s*<?([^)\s>]+)").unwrap();
+    // Not `[^1]: ...` footnotes
+    let reference = Regex::new(r"^(\s{0,3}\[[^\]^][^\]]*\]:\s*)<?([^\s>]+)").unwrap();
+    let fence = Regex::new(r"^\s{0,3}(```|~~~)").unwrap();
+    let indented = Regex::new(r"^( {4}|\t)").unwrap();
+    let wiki = Regex::new(r"\[\[([^\[\]|\n]+)(?:\|([^\[\]\n]+))?\]\]").unwrap();
+
+    let mut broken = vec![];
+    let mut in_fence = false;
+    let mut in_indented = false;
+    let mut after_blank = true;
+    let mut out = String::with_capacity(source.len());
+
+    for (i, line) in source.split_inclusive('\n').enumerate() {
+        if fence.is_match(line) {
+            in_fence = !in_fence;
+        }
+        // An indented code block starts after a blank line and runs through blank lines
+        let blank = line.trim().is_empty();
+        in_indented = !in_fence
+            && (indented.is_match(line) && (after_blank || in_indented) || blank && in_indented);
+        after_blank = blank;
+        if in_fence || in_indented {
+            out.push_str(line);
+            continue;
+        }
+
+        let mut resolve = |target: &str| match resolve_link(md_path, target, ctx) {
+            Ok(url) => url,
+            Err(reason) => {
+                broken.push(BrokenLink {
+                    line: i + 1,
+                    target: target.to_owned(),
+                    reason: reason.to_owned(),
+                });
+                target.to_owned()
+            }
+        };
+        let line = reference.replace(line, |caps: &Captures| {
+            format!("{}{}", &caps[1], resolve(&caps[2]))
+        });
+        let line = outside_code_spans(&line, |text| {
+            inline
+                .replace_all(text, |caps: &Captures| {
+                    caps[0].replace(&caps[1], &resolve(&caps[1]))
+                })
+                .into_owned()
+        });
+        // After the other links, whose targets would otherwise be resolved again
+        let line = outside_code_spans(&line, |text| {
+            wiki.replace_all(text, |caps: &Captures| {
+                let target = caps[1].trim();
+                match resolve_wiki_link(target, ctx) {
+                    Ok(post) => format!(
+                        "[{}]({})",
+                        caps.get(2).map_or_else(
+                            || escape_link_text(&post.title),
+                            |x| x.as_str().trim().to_owned()
+                        ),
+                        relative_link(ctx.dest, ctx.build_dir, &post.url)
+                    ),
+                    Err(reason) => {
+                        broken.push(BrokenLink {
+                            line: i + 1,
+                            target: caps[0].to_owned(),
+                            reason,
+                        });
+                        caps[0].to_owned()
+                    }
+                }
+            })
+            .into_owned()
+        });
+        out.push_str(&line);
+    }
+
+    if broken.is_empty() {
+        Ok(out)
+    } else {
+        Err(MdError::BrokenLinks {
+            path: md_path.into(),
+            links: broken,
+        })
+    }
+}
+
+/// `line` with `f` applied to the text around its inline code spans, which are kept as they are
+fn outside_code_spans(line: &str, mut f: impl FnMut(&str) -> String) -> String {
+    let ticks_at = |i: usize| line[i..].bytes().take_while(|&b| b == b'`').count();
+    let mut out = String::with_capacity(line.len());
+    let mut text_start = 0;
+    let mut i = 0;
+    while let Some(offset) = line[i..].find('`') {
+        let start = i + offset;
+        let ticks = ticks_at(start);
+        i = start + ticks;
+        // A span ends at the next run of exactly as many backticks; without one they're literal
+        let mut j = i;
+        while let Some(offset) = line[j..].find('`') {
+            let end = j + offset;
+            let run = ticks_at(end);
+            if run == ticks {
+                out.push_str(&f(&line[text_start..start]));
+                out.push_str(&line[start..end + run]);
+                text_start = end + run;
+                i = text_start;
+                break;
+            }
+            j = end + run;
+        }
+    }
+    out.push_str(&f(&line[text_start..]));
+    out
+}
+
+/// Output URL for a link target, relative to the linking post; external and root-relative links are left alone
+fn resolve_link(
+    md_path: &Path,
+    target: &str,
+    ctx: &LinkContext,
+) -> std::result::Result<String, &'static str> {
+    let scheme = Regex::new(r"^[[:alpha:]][[:alnum:]+.-]*:").unwrap();
+    if target.starts_with('#') || target.starts_with('/') || scheme.is_match(target) {
+        return Ok(target.to_owned());
+    }
+
+    let (file, fragment) = match target.find(['#', '?']) {
+        Some(i) => target.split_at(i),
+        None => (target, ""),
+    };
+    let path = normalize(&md_path.parent().unwrap().join(file));
+
+    let url = if path.extension().is_some_and(|x| x == "md") {
+        let post = ctx
+            .collections
+            .iter()
+            .flat_map(|x| &x.posts)
+            .find(|x| normalize(&x.path) == path);
+        match post {
+            Some(post) => post.url.clone(),
+            None if path.is_file() => mirrored(&path, &normalize(ctx.src_dir)),
+            None => return Err("no such markdown source"),
+        }
+    } else if path.exists() {
+        diff_paths(&path, normalize(ctx.src_dir))
+            .filter(|x| !x.starts_with(".."))
+            .ok_or("attachment outside of src")?
+            .to_string_lossy()
+            .replace('\\', "/")
+    } else {
+        return Err("no such attachment");
+    };
+
+    Ok(relative_link(ctx.dest, ctx.build_dir, &url) + fragment)
+}
+
+/// The one post whose title (ignoring case) or slug is `target`
+fn resolve_wiki_link<'a>(
+    target: &str,
+    ctx: &LinkContext<'a>,
+) -> std::result::Result<&'a MdInfo, String> {
+    let title = target.to_lowercase();
+    let matches = ctx
+        .collections
+        .iter()
+        .flat_map(|x| &x.posts)
+        .filter(|x| x.title.to_lowercase() == title || x.slug == target)
+        .collect::<Vec<_>>();
+    match matches.as_slice() {
+        [post] => Ok(post),
+        [] => Err("no post with this title or slug".to_owned()),
+        _ => Err(format!(
+            "ambiguous, matches {}",
+            matches
+                .iter()
+                .map(|x| x.path.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+    }
+}
+
+/// `text` as markdown link text that shows as written
+fn escape_link_text(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('[', "\\[")
+        .replace(']', "\\]")
+}
+
+/// Lexically resolves `.` and `..` so paths built from different directories compare equal
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut res = PathBuf::new();
+    for c in path.components() {
+        match c {
+            Component::CurDir => {}
+            Component::ParentDir if res.file_name().is_some() => {
+                res.pop();
+            }
+            // There is nothing above the root
+            Component::ParentDir if res.has_root() && res.parent().is_none() => {}
+            c => res.push(c),
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_resolves_dots() {
+        assert_eq!(
+            normalize(Path::new("src/posts/../notes/./a.md")),
+            PathBuf::from("src/notes/a.md")
+        );
+        assert_eq!(normalize(Path::new("./a/b/..")), PathBuf::from("a"));
+    }
+
+    #[test]
+    fn normalize_keeps_leading_parents() {
+        assert_eq!(normalize(Path::new("../../a")), PathBuf::from("../../a"));
+        assert_eq!(normalize(Path::new("a/../../b")), PathBuf::from("../b"));
+        assert_eq!(normalize(Path::new("/../a")), PathBuf::from("/a"));
+    }
+
+    fn upper(line: &str) -> String {
+        outside_code_spans(line, str::to_uppercase)
+    }
+
+    #[test]
+    fn code_spans_are_kept() {
+        assert_eq!(upper("a `b` c"), "A `b` C");
+        assert_eq!(upper("``x ` y`` z"), "``x ` y`` Z");
+        assert_eq!(upper("`a` and `b`"), "`a` AND `b`");
+    }
+
+    #[test]
+    fn unclosed_backticks_are_text() {
+        assert_eq!(upper("a ` b"), "A ` B");
+        assert_eq!(upper("``a` b"), "``A` B");
+        assert_eq!(upper("`` a `b` c"), "`` A `b` C");
+    }
+
+    fn posts(titles: &[(&str, &str)]) -> [Collection; 1] {
+        [Collection {
+            name: "posts".to_owned(),
+            dir: PathBuf::from("src/posts"),
+            layout: None,
+            feed: false,
+            posts: titles
+                .iter()
+                .map(|&(title, slug)| MdInfo {
+                    title: title.to_owned(),
+                    slug: slug.to_owned(),
+                    path: PathBuf::from(format!("src/posts/{slug}.md")),
+                    url: format!("blog/{slug}/"),
+                    ..Default::default()
+                })
+                .collect(),
+        }]
+    }
+
+    fn ctx(collections: &[Collection]) -> LinkContext<'_> {
+        LinkContext {
+            src_dir: Path::new("src"),
+            build_dir: Path::new("static"),
+            dest: Path::new("static/blog/a/index.html"),
+            collections,
+        }
+    }
+
+    fn wiki(collections: &[Collection], target: &str) -> std::result::Result<String, String> {
+        resolve_wiki_link(target, &ctx(collections)).map(|x| x.slug.clone())
+    }
+
+    #[test]
+    fn wiki_links_match_title_ignoring_case_or_slug() {
+        let collections = posts(&[("Other Post", "other"), ("Ärger über Öl", "oel")]);
+        assert_eq!(wiki(&collections, "other post").unwrap(), "other");
+        assert_eq!(wiki(&collections, "other").unwrap(), "other");
+        assert_eq!(wiki(&collections, "ärger ÜBER öl").unwrap(), "oel");
+        assert!(wiki(&collections, "Other").is_err());
+    }
+
+    #[test]
+    fn ambiguous_wiki_links_are_errors() {
+        let collections = posts(&[("Notes", "a"), ("notes", "b")]);
+        let err = wiki(&collections, "Notes").unwrap_err();
+        assert!(err.starts_with("ambiguous"), "{err}");
+    }
+
+    #[test]
+    fn wiki_links_in_code_spans_are_kept() {
+        let collections = posts(&[("A [draft] post", "draft")]);
+        let out = rewrite_links(
+            Path::new("src/posts/a.md"),
+            "[[draft]] `[[nope]]`\n",
+            &ctx(&collections),
+        )
+        .unwrap();
+        assert_eq!(out, "[A \\[draft\\] post](../draft/) `[[nope]]`\n");
+    }
+
+    fn rewrite(source: &str) -> Result<String> {
+        rewrite_links(Path::new("src/posts/a.md"), source, &ctx(&[]))
+    }
+
+    #[test]
+    fn footnotes_are_not_references() {
+        let source = "Text[^1].\n\n[^1]: This is the footnote text.\n";
+        assert_eq!(rewrite(source).unwrap(), source);
+    }
+
+    #[test]
+    fn indented_code_blocks_are_kept() {
+        let source = "Text\n\n    [link](some_file.md)\n\n\t[[Some Post]]\n";
+        assert_eq!(rewrite(source).unwrap(), source);
+        // A paragraph continuation or a line after the block is no code
+        assert!(rewrite("Text\n    [link](some_file.md)\n").is_err());
+        assert!(rewrite("    code\n\n[link](some_file.md)\n").is_err());
+    }
+}
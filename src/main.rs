@@ -1,28 +1,37 @@
+mod backlinks;
+mod build;
+mod cache;
+mod check;
+mod collection;
 mod config;
 mod default;
+mod diagnostic;
+mod highlight;
 mod html;
+mod ignore;
+mod links;
 mod markdown;
+mod permalink;
+mod redirect;
+mod related;
 mod rss;
+mod series;
+mod toc;
 
 use std::{
-    fs::{copy, create_dir, create_dir_all, read_dir, remove_dir_all, write},
-    path::{Path, PathBuf},
-    process::Command,
+    fs::remove_dir_all,
+    io,
+    path::PathBuf,
+    process::{Command, ExitCode},
 };
 
-use chrono::{DateTime, Utc};
 use clap::{Parser, Subcommand};
-use pathdiff::diff_paths;
-
-use rss_gen::{RssData, RssItem, RssVersion, generate_rss};
 
 use crate::{
-    config::{Config, ConfigError, read_config},
-    html::{HtmlError, generate_substituted_html},
-    markdown::{
-        MdError, add_meta_to_post_html, get_mdinfos_for_path, render_to_html, truncate_content,
-    },
-    rss::add_rss_meta,
+    build::{BuildErrors, BuildOptions, build, clean, default_jobs, diff_builds},
+    cache::CACHE_DIR,
+    config::{ConfigError, DEFAULT_COLLECTION, read_config},
+    markdown::MdError,
 };
 
 #[derive(Parser)]
@@ -43,21 +52,51 @@ enum Commands {
         /// Output dir
         #[arg(short, long)]
         output_dir: Option<PathBuf>,
+
+        /// Number of posts rendered in parallel (defaults to the number of CPUs)
+        #[arg(short, long)]
+        jobs: Option<usize>,
+
+        /// Builds a second time without the render cache and fails if the outputs differ
+        #[arg(long)]
+        verify_reproducible: bool,
     },
     // / Creates new site
     Init,
+    /// Builds into a temporary directory and reports broken links and unused files
+    Check,
+    /// Removes the output dir except the files matching `keep`, or the render cache with `--cache`
+    Clean {
+        /// Output dir
+        #[arg(short, long)]
+        output_dir: Option<PathBuf>,
+
+        /// Clear the render cache instead of the output dir
+        #[arg(long)]
+        cache: bool,
+    },
     /// Creates new post
     ///
     Post {
         name: String,
 
+        /// Collection to add the post to
+        #[arg(short, long, default_value = DEFAULT_COLLECTION)]
+        collection: String,
+
         #[arg(long, default_value_t = true)]
         open_in_editor: bool,
     },
 }
 
-fn main() -> Result<()> {
-    entry()
+fn main() -> ExitCode {
+    match entry() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+    }
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -65,11 +104,27 @@ pub type Result<T> = std::result::Result<T, Error>;
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error(transparent)]
-    Build(#[from] BuildError),
+    Build(#[from] BuildErrors),
     #[error(transparent)]
     Config(#[from] ConfigError),
     #[error(transparent)]
     Md(#[from] MdError),
+    #[error("Unknown collection '{0}'")]
+    UnknownCollection(String),
+    #[error("Check found {0} problem(s)")]
+    CheckFailed(usize),
+    #[error("Build is not reproducible: {0} file(s) differ")]
+    NotReproducible(usize),
+    #[error("Cannot clean {}: {source}", path.display())]
+    Clean { path: PathBuf, source: io::Error },
+}
+
+/// `res` of clearing `path`, which is fine if it doesn't exist
+fn cleaned(path: PathBuf, res: io::Result<()>) -> Result<()> {
+    match res {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(Error::Clean { path, source: e }),
+        _ => Ok(()),
+    }
 }
 
 fn entry() -> Result<()> {
@@ -80,18 +135,68 @@ fn entry() -> Result<()> {
         _ => {
             let c = read_config(&cli.path.join("config.json"))?;
             match &cli.command {
-                Commands::Build { output_dir } => {
-                    if let Some(path) = output_dir {
-                        build(&cli.path, &path, &c)?;
+                Commands::Build {
+                    output_dir,
+                    jobs,
+                    verify_reproducible,
+                } => {
+                    let opts = BuildOptions {
+                        jobs: jobs.unwrap_or_else(default_jobs),
+                        cache: true,
+                    };
+                    let output_dir = output_dir.clone().unwrap_or(cli.path.join("static"));
+                    let summary = build(&cli.path, &output_dir, &c, &opts)?;
+                    println!("{summary}");
+
+                    if *verify_reproducible {
+                        let second =
+                            std::env::temp_dir().join(format!("ssg-verify-{}", std::process::id()));
+                        let opts = BuildOptions {
+                            cache: false,
+                            ..opts
+                        };
+                        let res = build(&cli.path, &second, &c, &opts)
+                            .map(|_| diff_builds(&output_dir, &second, &c.keep_patterns()));
+                        let _ = remove_dir_all(&second);
+                        let differences = res?;
+                        for difference in &differences {
+                            println!("{difference}");
+                        }
+                        if !differences.is_empty() {
+                            return Err(Error::NotReproducible(differences.len()));
+                        }
+                    }
+                }
+                Commands::Clean { output_dir, cache } => {
+                    if *cache {
+                        let path = cli.path.join(CACHE_DIR);
+                        cleaned(path.clone(), remove_dir_all(&path))?;
                     } else {
-                        build(&cli.path, &cli.path.join("static"), &c)?;
+                        let output_dir = output_dir.clone().unwrap_or(cli.path.join("static"));
+                        let res = clean(&output_dir, &c.keep_patterns());
+                        cleaned(output_dir, res)?;
+                    }
+                }
+                Commands::Check => {
+                    let problems = check::check(&cli.path, &c)?;
+                    for problem in &problems {
+                        println!("{problem}");
+                    }
+                    if !problems.is_empty() {
+                        return Err(Error::CheckFailed(problems.len()));
                     }
                 }
                 Commands::Post {
                     name,
+                    collection,
                     open_in_editor,
                 } => {
-                    let md_path = markdown::create_post(name, &cli.path.join(&c.posts_dir))?;
+                    let collections = c.collections();
+                    let dir = &collections
+                        .get(collection)
+                        .ok_or_else(|| Error::UnknownCollection(collection.clone()))?
+                        .dir;
+                    let md_path = markdown::create_post(name, &cli.path.join(dir))?;
                     if *open_in_editor {
                         if let Ok(editor) = std::env::var("EDITOR") {
                             Command::new(editor).arg(&md_path).status().ok();
@@ -106,105 +211,3 @@ fn entry() -> Result<()> {
     }
     Ok(())
 }
-
-#[derive(Debug, thiserror::Error)]
-pub enum BuildError {
-    #[error(transparent)]
-    Md(#[from] MdError),
-    #[error(transparent)]
-    Html(#[from] HtmlError),
-}
-
-fn build(site_dir: &Path, build_dir: &Path, c: &Config) -> std::result::Result<(), BuildError> {
-    let posts_dir = site_dir.join(&c.posts_dir);
-    let components_dir = site_dir.join(&c.components_dir);
-    let styles_css = site_dir.join(&c.styles_css);
-
-    let src_dir = site_dir.join("src");
-    let _ = remove_dir_all(&build_dir);
-    create_dir(&build_dir).unwrap();
-    let blacklist = vec![components_dir.clone()];
-
-    let mut rss_data = RssData::new(Some(RssVersion::RSS2_0))
-        .title(&c.site_name)
-        .link(&c.hosted_url)
-        .description(&c.description);
-
-    let mut stack = vec![src_dir.clone()];
-    while let Some(path) = stack.pop() {
-        for entry in read_dir(path).unwrap() {
-            let entry = entry.unwrap();
-            let p = entry.path();
-
-            if blacklist.iter().all(|x| x != &p) {
-                if p.is_dir() {
-                    stack.push(p);
-                } else {
-                    let mut dest = build_dir.join(diff_paths(&p, &src_dir).unwrap());
-                    let _ = create_dir_all(&dest.parent().unwrap());
-
-                    match p.extension().and_then(|s| s.to_str()) {
-                        Some("html") => {
-                            generate_substituted_html(&p, &dest, &posts_dir, &components_dir, &c)?;
-                        }
-                        Some("md") => {
-                            let styles_css =
-                                build_dir.join(diff_paths(&styles_css, &src_dir).unwrap());
-                            dest.set_extension("html");
-                            let html = render_to_html(&p, &dest, Some(&styles_css), None, None);
-
-                            let md_infos = get_mdinfos_for_path(p.parent().unwrap())?;
-                            let md_info = md_infos.iter().filter(|c| c.path == p).next().unwrap();
-
-                            let post_url = c.hosted_url.clone()
-                                + "/"
-                                + &diff_paths(&dest, &build_dir)
-                                    .unwrap()
-                                    .to_string_lossy()
-                                    .to_string();
-
-                            write(
-                                dest,
-                                add_rss_meta(
-                                    &add_meta_to_post_html(
-                                        html,
-                                        md_info,
-                                        &post_url,
-                                        &c.og_image_url,
-                                        &c.site_name,
-                                    ),
-                                    &c.hosted_url,
-                                    &c.site_name,
-                                ),
-                            )
-                            .unwrap();
-
-                            rss_data.add_item(
-                                RssItem::new()
-                                    .title(&md_info.title)
-                                    .description(truncate_content(&md_info.content, 80))
-                                    .guid(&post_url)
-                                    .pub_date(
-                                        DateTime::<Utc>::from_naive_utc_and_offset(
-                                            md_info.date.and_hms_opt(0, 0, 0).unwrap(),
-                                            Utc,
-                                        )
-                                        .to_rfc2822(),
-                                    )
-                                    .link(&post_url),
-                            );
-                        }
-                        Some(_) => {
-                            copy(&p, &dest).unwrap();
-                        }
-                        None => panic!("{}", p.display()),
-                    };
-                }
-            }
-        }
-    }
-    // gen rss
-    //
-    write(build_dir.join("feed.xml"), generate_rss(&rss_data).unwrap()).unwrap();
-    Ok(())
-}
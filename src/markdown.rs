@@ -8,25 +8,99 @@ use std::{
     fs::{File, read_dir, read_to_string},
     io::Write,
     path::{Path, PathBuf},
-    process::Command,
+    process::{Command, Stdio},
     sync::{Mutex, OnceLock},
 };
 use thiserror::Error;
 
+use crate::{
+    cache::RenderCache,
+    config::{Config, MathMode, PandocConfig, one_or_many},
+    diagnostic::{Diagnostic, Level},
+    ignore::Ignore,
+    links::BrokenLink,
+    permalink::default_slug,
+};
+
 pub type Result<T> = std::result::Result<T, MdError>;
 
 #[derive(Debug, Deserialize)]
 struct FrontMatter {
     title: String,
     date: String,
+    slug: Option<String>,
+    #[serde(default)]
+    aliases: Vec<String>,
+    #[serde(default)]
+    pandoc: PandocConfig,
+    /// Relative to the post, like pandoc's own `bibliography` metadata
+    #[serde(default, deserialize_with = "one_or_many")]
+    bibliography: Option<Vec<PathBuf>>,
+    csl: Option<PathBuf>,
+    #[serde(default)]
+    toc: bool,
+    series: Option<String>,
+    series_order: Option<i64>,
+    #[serde(default)]
+    tags: Vec<String>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Default)]
 pub struct MdInfo {
     pub date: NaiveDate,
     pub title: String,
     pub content: String,
     pub path: PathBuf,
+    pub slug: String,
+    /// Output location relative to the site root, assigned by `load_collections`
+    pub url: String,
+    /// Old locations redirecting to this post
+    pub aliases: Vec<String>,
+    /// Overrides of the site's pandoc settings
+    pub pandoc: PandocConfig,
+    /// Table of contents inserted before the first heading
+    pub toc: bool,
+    /// Words of the plain-text body, counting each CJK character as one
+    pub words: usize,
+    /// CJK characters among `words`, read at their own rate
+    pub cjk_chars: usize,
+    /// Name of the multi-part series the post belongs to
+    pub series: Option<String>,
+    /// Position in the series; posts without one follow by date
+    pub series_order: Option<i64>,
+    /// Topics shared with related posts
+    pub tags: Vec<String>,
+}
+
+impl MdInfo {
+    /// Estimated minutes to read the post, at least 1
+    pub fn reading_minutes(&self, c: &Config) -> usize {
+        let minutes = (self.words - self.cjk_chars) as f64 / c.words_per_minute.max(1) as f64
+            + self.cjk_chars as f64 / c.cjk_chars_per_minute.max(1) as f64;
+        (minutes.ceil() as usize).max(1)
+    }
+}
+
+impl Ord for MdInfo {
+    /// By date, then title, then path, so the order never depends on the filesystem
+    fn cmp(&self, other: &Self) -> cmp::Ordering {
+        (self.date, &self.title, &self.path).cmp(&(other.date, &other.title, &other.path))
+    }
+}
+
+// Equal exactly when `cmp` says so, as `sort` and `dedup` expect
+impl PartialEq for MdInfo {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for MdInfo {}
+
+impl PartialOrd for MdInfo {
+    fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
 }
 
 #[derive(Debug, Error)]
@@ -38,51 +112,119 @@ pub enum MdError {
         source: std::io::Error,
     },
 
-    #[error("Missing or malformed front matter in {path}")]
-    MissingFrontMatter { path: PathBuf },
+    #[error("{diagnostic}")]
+    MissingFrontMatter {
+        path: PathBuf,
+        diagnostic: Box<Diagnostic>,
+    },
 
-    #[error("Invalid YAML front matter in {path}: {source}")]
+    #[error("{diagnostic}")]
     InvalidYaml {
         path: PathBuf,
+        diagnostic: Box<Diagnostic>,
         #[source]
         source: serde_yaml::Error,
     },
 
-    #[error("Invalid date '{date}' in {path}: {source}")]
+    #[error("Unresolved links in {path}:{}", format_broken_links(links))]
+    BrokenLinks {
+        path: PathBuf,
+        links: Vec<BrokenLink>,
+    },
+
+    #[error("{diagnostic}")]
     InvalidDate {
         path: PathBuf,
         date: String,
+        diagnostic: Box<Diagnostic>,
         #[source]
         source: ParseError,
     },
+
+    #[error("Could not run pandoc for {path}: {source}")]
+    PandocSpawn {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("Pandoc failed on {path}: {stderr}")]
+    Pandoc { path: PathBuf, stderr: String },
+}
+
+impl MdError {
+    pub fn path(&self) -> &Path {
+        match self {
+            MdError::Io { path, .. }
+            | MdError::MissingFrontMatter { path, .. }
+            | MdError::InvalidYaml { path, .. }
+            | MdError::BrokenLinks { path, .. }
+            | MdError::InvalidDate { path, .. }
+            | MdError::PandocSpawn { path, .. }
+            | MdError::Pandoc { path, .. } => path,
+        }
+    }
+}
+
+fn format_broken_links(links: &[BrokenLink]) -> String {
+    links
+        .iter()
+        .map(|x| format!("\n  line {}: {} ({})", x.line, x.target, x.reason))
+        .collect()
 }
 
 // Simple per-process cache for component files
 static POST_CACHE: OnceLock<Mutex<HashMap<PathBuf, Vec<MdInfo>>>> = OnceLock::new();
 
-pub fn get_mdinfos_for_path(posts_dir: &Path) -> Result<Vec<MdInfo>> {
+/// Every post below `posts_dir` that could be read, plus the errors for those that couldn't
+pub fn get_mdinfos_for_path(posts_dir: &Path, ignore: &Ignore) -> (Vec<MdInfo>, Vec<MdError>) {
     let cache = POST_CACHE.get_or_init(|| Mutex::new(HashMap::new()));
     let mut map = cache.lock().unwrap();
 
     if let Some(s) = map.get(posts_dir) {
-        return Ok(s.to_vec());
+        return (s.to_vec(), vec![]);
     }
 
     let mut stack = vec![PathBuf::from(posts_dir)];
     let mut res: Vec<MdInfo> = vec![];
+    let mut errors = vec![];
     while let Some(path) = stack.pop() {
-        for entry in read_dir(path).unwrap() {
-            let entry = entry.unwrap();
-            let p = entry.path();
+        let entries = match read_dir(&path) {
+            Ok(entries) => entries,
+            Err(e) => {
+                errors.push(MdError::Io { path, source: e });
+                continue;
+            }
+        };
+        for entry in entries {
+            let p = match entry {
+                Ok(entry) => entry.path(),
+                Err(e) => {
+                    errors.push(MdError::Io {
+                        path: path.clone(),
+                        source: e,
+                    });
+                    continue;
+                }
+            };
+            if ignore.is_ignored(&p) {
+                continue;
+            }
             if p.is_dir() {
                 stack.push(p);
-            } else if p.extension().unwrap() == "md" {
-                res.push(get_md_info(&p)?);
+            } else if p.extension().is_some_and(|x| x == "md") {
+                match get_md_info(&p) {
+                    Ok(info) => res.push(info),
+                    Err(e) => errors.push(e),
+                }
             }
         }
     }
-    map.insert(posts_dir.to_path_buf(), res.to_vec());
-    Ok(res)
+    res.sort();
+    if errors.is_empty() {
+        map.insert(posts_dir.to_path_buf(), res.to_vec());
+    }
+    (res, errors)
 }
 
 // user input name -> path to dir -> markdown file
@@ -96,9 +238,9 @@ pub fn create_post(post_name: &str, output_dir_path: &Path) -> Result<PathBuf> {
         .filter(|x| x.is_alphanumeric() || *x == separator)
         .collect();
 
-    let local: DateTime<Local> = Local::now();
-    let file_safe_date = local.format("%y_%m_%d");
-    let md_date = local.format("%A %d %B %Y");
+    let now = now();
+    let file_safe_date = now.format("%y_%m_%d");
+    let md_date = now.format("%A %d %B %Y");
 
     let md_path = output_dir_path.join(format!("{file_safe_date}_{file_safe_name}.md"));
 
@@ -110,29 +252,218 @@ pub fn create_post(post_name: &str, output_dir_path: &Path) -> Result<PathBuf> {
     Ok(md_path)
 }
 
+/// Local time, or `SOURCE_DATE_EPOCH` (in UTC) when set so runs can be reproduced
+fn now() -> DateTime<FixedOffset> {
+    std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|x| x.trim().parse().ok())
+        .and_then(|secs| DateTime::<Utc>::from_timestamp(secs, 0))
+        .map_or_else(|| Local::now().fixed_offset(), |x| x.fixed_offset())
+}
+
+/// How a post is handed to pandoc
+#[derive(Default)]
+pub struct RenderOptions<'a> {
+    pub css_path: Option<&'a Path>,
+    pub header_path: Option<&'a Path>,
+    pub footer_path: Option<&'a Path>,
+    /// Whole HTML document rather than a fragment for a layout
+    pub standalone: bool,
+    /// Site settings merged with the post's, paths resolved
+    pub pandoc: PandocConfig,
+    /// Code blocks are highlighted after pandoc, so its own highlighter is turned off
+    pub highlight: bool,
+}
+
+/// Renders `source` (the contents of `md_path` after link rewriting) through pandoc
 pub fn render_to_html(
     md_path: &Path,
+    source: &str,
     output_path: &Path,
-    css_path: Option<&Path>,
-    header_path: Option<&Path>,
-    footer_path: Option<&Path>,
-) -> String {
+    opts: &RenderOptions,
+    cache: Option<&RenderCache>,
+) -> Result<String> {
+    let pandoc = &opts.pandoc;
     let mut c = Command::new("pandoc");
-    c.arg(md_path).arg("-s").arg("--mathjax");
+    if let Some(from) = &pandoc.from {
+        c.args(["-f", from]);
+    }
+    // The source arrives over stdin, so relative images are looked up next to the post
+    c.arg("--resource-path")
+        .arg(md_path.parent().unwrap_or(Path::new(".")));
+    c.arg(match pandoc.math.unwrap_or_default() {
+        MathMode::Mathjax => "--mathjax",
+        MathMode::Mathml => "--mathml",
+        MathMode::Katex => "--katex",
+        MathMode::Webtex => "--webtex",
+    });
+    if opts.standalone {
+        c.arg("-s");
+    }
+    if let Some(template) = &pandoc.template {
+        c.arg("--template").arg(template);
+    }
+    if opts.highlight {
+        c.arg("--no-highlight");
+    } else if let Some(style) = &pandoc.highlight_style {
+        c.args(["--highlight-style", style]);
+    }
+    if pandoc.toc == Some(true) {
+        c.arg("--toc");
+    }
+    for filter in pandoc.lua_filters.iter().flatten() {
+        c.arg("--lua-filter").arg(filter);
+    }
+    // Given on the command line, they override the front matter values pandoc also sees
+    if pandoc.bibliography.is_some() || pandoc.csl.is_some() {
+        c.arg("--citeproc");
+    }
+    for bibliography in pandoc.bibliography.iter().flatten() {
+        c.arg("--bibliography").arg(bibliography);
+    }
+    if let Some(csl) = &pandoc.csl {
+        c.arg("--csl").arg(csl);
+    }
 
-    if let Some(css_path) = css_path {
+    if let Some(css_path) = opts.css_path {
         c.arg("-c");
         c.arg(diff_paths(css_path, output_path.parent().unwrap()).unwrap());
     }
-    if let Some(header_path) = header_path {
+    if let Some(header_path) = opts.header_path {
         c.arg("-B");
         c.arg(header_path);
     }
-    if let Some(footer_path) = footer_path {
+    if let Some(footer_path) = opts.footer_path {
         c.arg("-A");
         c.arg(footer_path);
     }
-    String::from_utf8(c.output().unwrap().stdout).expect("Pandoc failed")
+    c.args(pandoc.args.iter().flatten());
+
+    // Free-form args may name files too, as in `--include-in-header=x.html` or `-H x.html`
+    let arg_files = pandoc
+        .args
+        .iter()
+        .flatten()
+        .map(|x| PathBuf::from(x.split_once('=').map_or(x.as_str(), |(_, value)| value)))
+        .filter(|x| x.is_file())
+        .collect::<Vec<_>>();
+    // Templates, filters, bibliographies and those files are hashed by content, so editing one
+    // re-renders its posts
+    let inputs = pandoc
+        .template
+        .iter()
+        .chain(pandoc.lua_filters.iter().flatten())
+        .chain(pandoc.bibliography.iter().flatten())
+        .chain(pandoc.csl.iter())
+        .chain(&arg_files)
+        .collect::<Vec<_>>();
+    let key = cache.map(|x| x.key(source, c.get_args(), &inputs));
+    if let (Some(cache), Some(key)) = (cache, &key)
+        && let Some(html) = cache.get(key)
+    {
+        return Ok(html);
+    }
+
+    c.stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+
+    let spawn_error = |e| MdError::PandocSpawn {
+        path: md_path.into(),
+        source: e,
+    };
+    let mut child = c.spawn().map_err(spawn_error)?;
+    // A pandoc that exits early closes stdin; its stderr says why
+    let _ = child.stdin.take().unwrap().write_all(source.as_bytes());
+    let output = child.wait_with_output().map_err(spawn_error)?;
+
+    if !output.status.success() {
+        return Err(MdError::Pandoc {
+            path: md_path.into(),
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+        });
+    }
+    let html = String::from_utf8_lossy(&output.stdout).into_owned();
+    if let (Some(cache), Some(key)) = (cache, &key) {
+        cache.put(key, &html);
+    }
+    Ok(html)
+}
+
+/// Script and stylesheet tags a layout needs to display the math in `html`, pandoc output
+/// for `mode`; empty for posts without math and for modes rendered at build time
+pub fn math_head(mode: MathMode, html: &str) -> String {
+    if !html.contains(r#"class="math "#) {
+        return String::new();
+    }
+    match mode {
+        MathMode::Mathml | MathMode::Webtex => String::new(),
+        MathMode::Mathjax => MATHJAX_HEAD.to_owned(),
+        MathMode::Katex => KATEX_HEAD.to_owned(),
+    }
+}
+
+const MATHJAX_HEAD: &str =
+    r#"<script defer src="https://cdn.jsdelivr.net/npm/mathjax@3/es5/tex-chtml-full.js"></script>"#;
+
+// What pandoc's own template does for `--katex`
+const KATEX_HEAD: &str = r#"<link rel="stylesheet" href="https://cdn.jsdelivr.net/npm/katex@0.16/dist/katex.min.css" />
+<script defer src="https://cdn.jsdelivr.net/npm/katex@0.16/dist/katex.min.js"></script>
+<script>document.addEventListener("DOMContentLoaded", function () {
+  for (const el of document.querySelectorAll("span.math")) {
+    katex.render(el.textContent, el, { displayMode: el.classList.contains("display"), throwOnError: false });
+  }
+});</script>"#;
+
+/// What a reader reads of a markdown body: no code blocks, markup, URLs or images
+pub fn plain_text(md: &str) -> String {
+    let replacements = [
+        (r"(?ms)^[ \t]*(```|~~~).*?^[ \t]*(```|~~~)[ \t]*$", ""),
+        (r"(?s)<!--.*?-->", ""),
+        (r"<[^>]*>", ""),
+        (r"!\[[^\]]*\]\([^)]*\)", ""),
+        (r"\[([^\]]*)\]\([^)]*\)", "$1"),
+        (r"(?m)^[ \t]*\[[^\]]+\]:.*$", ""),
+    ];
+    replacements
+        .iter()
+        .fold(md.to_owned(), |text, (pattern, with)| {
+            Regex::new(pattern)
+                .unwrap()
+                .replace_all(&text, *with)
+                .into_owned()
+        })
+}
+
+/// Words and CJK characters of `text`; CJK scripts don't separate words with spaces, so each
+/// character counts as a word
+fn count_words(text: &str) -> (usize, usize) {
+    let (mut words, mut cjk) = (0, 0);
+    let mut in_word = false;
+    for ch in text.chars() {
+        if is_cjk(ch) {
+            cjk += 1;
+            in_word = false;
+        } else if ch.is_alphanumeric() {
+            if !in_word {
+                words += 1;
+                in_word = true;
+            }
+        } else if !(in_word && matches!(ch, '\'' | '’' | '-' | '_')) {
+            in_word = false;
+        }
+    }
+    (words + cjk, cjk)
+}
+
+/// Han ideographs and Japanese kana; Hangul is written with spaces and counted like Latin
+pub fn is_cjk(ch: char) -> bool {
+    matches!(ch,
+        '\u{3040}'..='\u{30FF}'
+        | '\u{3400}'..='\u{4DBF}'
+        | '\u{4E00}'..='\u{9FFF}'
+        | '\u{F900}'..='\u{FAFF}'
+        | '\u{20000}'..='\u{2FA1F}')
 }
 
 pub fn truncate_content(content: &str, max_length: usize) -> String {
@@ -170,7 +501,7 @@ pub fn add_meta_to_post_html(
 }
 
 
-fn get_md_info(path: &Path) -> Result<MdInfo> {
+pub fn get_md_info(path: &Path) -> Result<MdInfo> {
     let contents = read_to_string(path).map_err(|e| MdError::Io {
         path: path.into(),
         source: e,
@@ -179,27 +510,96 @@ fn get_md_info(path: &Path) -> Result<MdInfo> {
 
     let caps = re
         .captures(&contents)
-        .ok_or_else(|| MdError::MissingFrontMatter { path: path.into() })?;
-    let fm_str = caps.get(1).unwrap().as_str();
+        .ok_or_else(|| MdError::MissingFrontMatter {
+            path: path.into(),
+            diagnostic: Box::new(
+                Diagnostic::at(
+                    Level::Error,
+                    "missing or malformed front matter",
+                    path,
+                    &contents,
+                    0,
+                    contents.find('\n').unwrap_or(contents.len()),
+                )
+                .with_hint("posts start with a `---` block containing `title:` and `date:`, closed by `---`"),
+            ),
+        })?;
+    let fm_match = caps.get(1).unwrap();
+    let fm_str = fm_match.as_str();
     let content = caps.get(2).unwrap().as_str();
 
-    let fm: FrontMatter = serde_yaml::from_str(fm_str).map_err(|e| MdError::InvalidYaml {
-        path: path.into(),
-        source: e,
+    let fm: FrontMatter = serde_yaml::from_str(fm_str).map_err(|e| {
+        let offset = fm_match.start() + e.location().map_or(0, |l| l.index());
+        MdError::InvalidYaml {
+            path: path.into(),
+            diagnostic: Box::new(Diagnostic::at(
+                Level::Error,
+                format!("invalid YAML front matter: {e}"),
+                path,
+                &contents,
+                offset,
+                1,
+            )),
+            source: e,
+        }
     })?;
-    let date = parse_date(&fm.date).map_err(|e| MdError::InvalidDate {
-        path: path.into(),
-        date: fm.date.clone(),
-        source: e,
+    let date = parse_date(&fm.date).map_err(|e| {
+        // Point at the value of the `date:` key
+        let value = Regex::new(r"(?m)^date:[ \t]*(.*?)[ \t]*$")
+            .unwrap()
+            .captures(fm_str)
+            .and_then(|caps| caps.get(1));
+        let (offset, len) = value.map_or((0, 1), |m| (fm_match.start() + m.start(), m.len()));
+        MdError::InvalidDate {
+            path: path.into(),
+            date: fm.date.clone(),
+            diagnostic: Box::new(
+                Diagnostic::at(
+                    Level::Error,
+                    format!("invalid date '{}': {e}", fm.date),
+                    path,
+                    &contents,
+                    offset,
+                    len,
+                )
+                .with_hint(DATE_FORMATS_HINT),
+            ),
+            source: e,
+        }
     })?;
+    // Absolute, so `PandocConfig::resolve` leaves them alone
+    let relative_to_post = |p: &PathBuf| {
+        let p = path.parent().unwrap_or(Path::new("")).join(p);
+        std::path::absolute(&p).unwrap_or(p)
+    };
+    let mut pandoc = fm.pandoc;
+    if let Some(bibliography) = &fm.bibliography {
+        pandoc.bibliography = Some(bibliography.iter().map(relative_to_post).collect());
+    }
+    if let Some(csl) = &fm.csl {
+        pandoc.csl = Some(relative_to_post(csl));
+    }
+    let (words, cjk_chars) = count_words(&plain_text(content));
     Ok(MdInfo {
         title: fm.title,
-        date: date,
+        date,
         content: content.to_string(),
         path: path.into(),
+        slug: fm.slug.unwrap_or_else(|| default_slug(path)),
+        url: String::new(),
+        aliases: fm.aliases,
+        pandoc,
+        toc: fm.toc,
+        words,
+        cjk_chars,
+        series: fm.series,
+        series_order: fm.series_order,
+        tags: fm.tags,
     })
 }
 
+const DATE_FORMATS_HINT: &str = "accepted formats are `Tuesday 16 September 2025` (%A %e %B %Y) and `16 September 2025` (%e %B %Y)";
+
 fn parse_date(date_str: &str) -> std::result::Result<chrono::NaiveDate, ParseError> {
     // Example date: "Tuesday 16 September 2025"
     // Format: weekday full name, space-padded day, month full name, year
@@ -207,3 +607,49 @@ fn parse_date(date_str: &str) -> std::result::Result<chrono::NaiveDate, ParseErr
     NaiveDate::parse_from_str(date_str, "%A %e %B %Y")
         .or_else(|_| NaiveDate::parse_from_str(date_str, "%e %B %Y"))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn words_are_split_on_spaces_and_punctuation() {
+        assert_eq!(count_words("Hello, world! 42 times"), (4, 0));
+        assert_eq!(count_words(""), (0, 0));
+        assert_eq!(count_words("  ...  "), (0, 0));
+    }
+
+    #[test]
+    fn apostrophes_and_hyphens_join_words() {
+        assert_eq!(count_words("don't well-known snake_case it’s"), (4, 0));
+        // Only between letters
+        assert_eq!(count_words("a - b 'c'"), (3, 0));
+    }
+
+    #[test]
+    fn cjk_characters_count_one_each() {
+        assert_eq!(count_words("日本語です"), (5, 5));
+        assert_eq!(count_words("Rust言語 is fun"), (5, 2));
+        // Hangul is written with spaces
+        assert_eq!(count_words("안녕 하세요"), (2, 0));
+    }
+
+    #[test]
+    fn plain_text_drops_code_markup_and_urls() {
+        let md = "Intro <b>bold</b> [link](https://x.org) ![pic](a.png)\n\n```rust\nlet x = 1;\n```\n\n[ref]: https://y.org\n<!-- note -->";
+        assert_eq!(count_words(&plain_text(md)), (3, 0));
+    }
+
+    #[test]
+    fn reading_time_is_at_least_a_minute() {
+        let c = Config::default();
+        let post = |words, cjk_chars| MdInfo {
+            words,
+            cjk_chars,
+            ..Default::default()
+        };
+        assert_eq!(post(0, 0).reading_minutes(&c), 1);
+        assert_eq!(post(400, 0).reading_minutes(&c), 2);
+        assert_eq!(post(1000, 1000).reading_minutes(&c), 2);
+    }
+}
@@ -0,0 +1,145 @@
+use std::path::{Path, PathBuf};
+
+use chrono::Datelike;
+use pathdiff::diff_paths;
+use regex::Regex;
+
+use crate::markdown::MdInfo;
+
+/// Fills `{year}`, `{month}`, `{day}`, `{slug}` and `{collection}` into a permalink pattern
+pub fn expand(pattern: &str, info: &MdInfo, collection: &str) -> String {
+    pattern
+        .replace("{year}", &format!("{:04}", info.date.year()))
+        .replace("{month}", &format!("{:02}", info.date.month()))
+        .replace("{day}", &format!("{:02}", info.date.day()))
+        .replace("{slug}", &info.slug)
+        .replace("{collection}", collection)
+        .trim_start_matches('/')
+        .to_owned()
+}
+
+/// URL of a page mirroring its source location under `src_dir`
+pub fn mirrored(src: &Path, src_dir: &Path) -> String {
+    let mut rel = diff_paths(src, src_dir).unwrap();
+    rel.set_extension("html");
+    rel.to_string_lossy().replace('\\', "/")
+}
+
+/// File a URL is written to; directory URLs get an `index.html`
+pub fn output_path(url: &str) -> PathBuf {
+    if url.is_empty() || url.ends_with('/') {
+        PathBuf::from(url).join("index.html")
+    } else {
+        PathBuf::from(url)
+    }
+}
+
+/// Link to `url` from the page written at `dest` inside `build_dir`
+pub fn relative_link(dest: &Path, build_dir: &Path, url: &str) -> String {
+    let rel = diff_paths(build_dir.join(url), dest.parent().unwrap())
+        .unwrap()
+        .to_string_lossy()
+        .replace('\\', "/");
+    match (rel.is_empty(), url.ends_with('/') || url.is_empty()) {
+        (true, _) => "./".to_owned(),
+        (false, true) => rel + "/",
+        (false, false) => rel,
+    }
+}
+
+/// Slug from a post file name: the `YY_MM_DD_` prefix `create_post` adds is dropped
+pub fn default_slug(path: &Path) -> String {
+    let stem = path.file_stem().unwrap().to_string_lossy();
+    let re = Regex::new(r"^\d{2}_\d{2}_\d{2}_").unwrap();
+    slugify(&re.replace(&stem, ""))
+}
+
+pub fn slugify(s: &str) -> String {
+    s.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|x| !x.is_empty())
+        .collect::<Vec<&str>>()
+        .join("-")
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    fn post(slug: &str, date: (i32, u32, u32)) -> MdInfo {
+        MdInfo {
+            slug: slug.to_owned(),
+            date: NaiveDate::from_ymd_opt(date.0, date.1, date.2).unwrap(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn expand_pads_dates_and_drops_leading_slash() {
+        let info = post("hello", (2025, 5, 2));
+        assert_eq!(
+            expand("/blog/{year}/{month}/{day}/{slug}/", &info, "posts"),
+            "blog/2025/05/02/hello/"
+        );
+        assert_eq!(
+            expand("{collection}/{slug}.html", &info, "notes"),
+            "notes/hello.html"
+        );
+    }
+
+    #[test]
+    fn expand_leaves_unknown_placeholders() {
+        let info = post("x", (2025, 1, 1));
+        assert_eq!(expand("{slug}/{nope}", &info, "posts"), "x/{nope}");
+    }
+
+    #[test]
+    fn output_path_adds_index_to_directories() {
+        assert_eq!(output_path("a/b/"), PathBuf::from("a/b/index.html"));
+        assert_eq!(output_path(""), PathBuf::from("index.html"));
+        assert_eq!(output_path("a/b.html"), PathBuf::from("a/b.html"));
+    }
+
+    #[test]
+    fn relative_link_between_pages() {
+        let build = Path::new("/out");
+        let dest = Path::new("/out/blog/2025/05/a/index.html");
+        assert_eq!(relative_link(dest, build, "blog/2025/05/b/"), "../b/");
+        assert_eq!(
+            relative_link(dest, build, "notes/x.html"),
+            "../../../../notes/x.html"
+        );
+        assert_eq!(
+            relative_link(Path::new("/out/index.html"), build, "feed.xml"),
+            "feed.xml"
+        );
+    }
+
+    #[test]
+    fn relative_link_to_own_directory() {
+        let build = Path::new("/out");
+        assert_eq!(
+            relative_link(Path::new("/out/a/index.html"), build, "a/"),
+            "./"
+        );
+        assert_eq!(relative_link(Path::new("/out/index.html"), build, ""), "./");
+    }
+
+    #[test]
+    fn default_slug_drops_date_prefix() {
+        assert_eq!(
+            default_slug(Path::new("posts/25_05_02_My_Post.md")),
+            "my-post"
+        );
+        assert_eq!(default_slug(Path::new("posts/About Me.md")), "about-me");
+    }
+
+    #[test]
+    fn slugify_collapses_punctuation() {
+        assert_eq!(slugify("  Hello, World!  "), "hello-world");
+        assert_eq!(slugify("Über Café"), "über-café");
+        assert_eq!(slugify("---"), "");
+    }
+}
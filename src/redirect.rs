@@ -0,0 +1,117 @@
+use std::{
+    fs::{create_dir_all, write},
+    io,
+    path::{Path, PathBuf},
+};
+
+use crate::{collection::Collection, config::Config, permalink::output_path};
+
+#[derive(Debug, thiserror::Error)]
+pub enum RedirectError {
+    #[error("I/O writing redirect {path}: {source}")]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("Redirect from '{from}' would overwrite {path}")]
+    Collision { from: String, path: PathBuf },
+}
+
+impl RedirectError {
+    pub fn path(&self) -> &Path {
+        match self {
+            RedirectError::Io { path, .. } | RedirectError::Collision { path, .. } => path,
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, RedirectError>;
+
+/// Old location (site-relative, no leading slash) -> target URL
+pub struct Redirect {
+    pub from: String,
+    pub to: String,
+}
+
+/// Redirects from post `aliases` and the `redirects` config map
+pub fn collect_redirects(collections: &[Collection], c: &Config) -> Vec<Redirect> {
+    let aliases = collections.iter().flat_map(|x| &x.posts).flat_map(|post| {
+        post.aliases.iter().map(|alias| Redirect {
+            from: alias.trim_start_matches('/').to_owned(),
+            to: absolute_url(&post.url, c),
+        })
+    });
+    let configured = c.redirects.iter().map(|(from, to)| Redirect {
+        from: from.trim_start_matches('/').to_owned(),
+        to: absolute_url(to, c),
+    });
+    aliases.chain(configured).collect()
+}
+
+fn absolute_url(url: &str, c: &Config) -> String {
+    if url.starts_with("http://") || url.starts_with("https://") {
+        url.to_owned()
+    } else {
+        c.site_url() + "/" + url.trim_start_matches('/')
+    }
+}
+
+/// Writes a meta-refresh stub for every redirect, plus the server-side lists enabled in config
+pub fn write_redirects(build_dir: &Path, redirects: &[Redirect], c: &Config) -> Result<()> {
+    for r in redirects {
+        let path = build_dir.join(output_path(&r.from));
+        if path.exists() {
+            return Err(RedirectError::Collision {
+                from: r.from.clone(),
+                path,
+            });
+        }
+        let _ = create_dir_all(path.parent().unwrap());
+        write(&path, redirect_stub(&r.to)).map_err(|e| RedirectError::Io { path, source: e })?;
+    }
+
+    let base_path = c.base_path();
+    if c.redirect_files.netlify {
+        let lines = redirects
+            .iter()
+            .map(|r| format!("{base_path}/{} {} 301\n", r.from, r.to))
+            .collect::<String>();
+        write_list(&build_dir.join("_redirects"), lines)?;
+    }
+    if c.redirect_files.nginx {
+        let lines = redirects
+            .iter()
+            .map(|r| format!("{base_path}/{} {};\n", r.from, r.to))
+            .collect::<String>();
+        write_list(&build_dir.join("redirects.map"), lines)?;
+    }
+    Ok(())
+}
+
+fn write_list(path: &Path, contents: String) -> Result<()> {
+    write(path, contents).map_err(|e| RedirectError::Io {
+        path: path.to_path_buf(),
+        source: e,
+    })
+}
+
+fn redirect_stub(to: &str) -> String {
+    format!(
+        r#"<!doctype html>
+<html lang="en">
+  <head>
+    <meta charset="utf-8" />
+    <title>Redirecting&hellip;</title>
+    <link rel="canonical" href="{to}" />
+    <meta http-equiv="refresh" content="0; url={to}" />
+    <meta name="robots" content="noindex" />
+  </head>
+  <body>
+    <p>This page has moved to <a href="{to}">{to}</a>.</p>
+  </body>
+</html>
+"#
+    )
+}
@@ -0,0 +1,196 @@
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap, HashSet},
+    path::PathBuf,
+};
+
+use crate::{
+    collection::Collection,
+    markdown::{MdInfo, is_cjk, plain_text},
+};
+
+/// The `count` most related posts of every post, keyed by source path
+///
+/// Each shared tag adds 1 to a pair's score and the cosine similarity of their TF-IDF weighted
+/// bodies adds up to 1 more, so tags decide first and the text breaks ties. Posts sharing
+/// nothing aren't related.
+pub fn related_posts(collections: &[Collection], count: usize) -> HashMap<PathBuf, Vec<MdInfo>> {
+    let mut posts = collections
+        .iter()
+        .flat_map(|x| &x.posts)
+        .collect::<Vec<_>>();
+    // Stable tie-breaks regardless of collection order
+    posts.sort();
+    let vectors = tf_idf(&posts);
+
+    posts
+        .iter()
+        .enumerate()
+        .map(|(i, post)| {
+            let tags = post.tags.iter().collect::<HashSet<_>>();
+            let mut scored = posts
+                .iter()
+                .enumerate()
+                .filter(|(j, _)| *j != i)
+                .map(|(j, other)| {
+                    let shared = other.tags.iter().filter(|x| tags.contains(x)).count();
+                    (shared as f64 + cosine(&vectors[i], &vectors[j]), *other)
+                })
+                .filter(|(score, _)| *score > 0.0)
+                .collect::<Vec<_>>();
+            scored.sort_by(|a, b| {
+                b.0.partial_cmp(&a.0)
+                    .unwrap_or(Ordering::Equal)
+                    .then_with(|| b.1.cmp(a.1))
+            });
+            let related = scored
+                .into_iter()
+                .take(count)
+                .map(|(_, x)| x.clone())
+                .collect();
+            (post.path.clone(), related)
+        })
+        .collect()
+}
+
+// Ordered, so sums of floats come out the same in every run
+type Vector = BTreeMap<String, f64>;
+
+/// Term frequency times inverse document frequency of every term, normalized to length 1
+fn tf_idf(posts: &[&MdInfo]) -> Vec<Vector> {
+    let terms = posts
+        .iter()
+        .map(|x| terms(&plain_text(&x.content)))
+        .collect::<Vec<_>>();
+    let mut df: HashMap<&str, usize> = HashMap::new();
+    for t in &terms {
+        for term in t.iter().map(String::as_str).collect::<HashSet<_>>() {
+            *df.entry(term).or_default() += 1;
+        }
+    }
+
+    let n = posts.len() as f64;
+    terms
+        .iter()
+        .map(|t| {
+            let mut v: Vector = BTreeMap::new();
+            for term in t {
+                *v.entry(term.clone()).or_default() += 1.0;
+            }
+            for (term, weight) in v.iter_mut() {
+                // Terms in every post carry no information
+                *weight *= (n / df[term.as_str()] as f64).ln();
+            }
+            let norm = v.values().map(|x| x * x).sum::<f64>().sqrt();
+            if norm > 0.0 {
+                v.values_mut().for_each(|x| *x /= norm);
+            }
+            v
+        })
+        .collect()
+}
+
+fn cosine(a: &Vector, b: &Vector) -> f64 {
+    let (small, large) = if a.len() < b.len() { (a, b) } else { (b, a) };
+    small
+        .iter()
+        .filter_map(|(term, x)| large.get(term).map(|y| x * y))
+        .sum()
+}
+
+/// Lowercased words of at least 3 characters, and single CJK characters
+fn terms(text: &str) -> Vec<String> {
+    text.split(|ch: char| !ch.is_alphanumeric())
+        .flat_map(|word| {
+            if word.chars().any(is_cjk) {
+                word.chars().map(String::from).collect()
+            } else if word.chars().count() >= 3 {
+                vec![word.to_lowercase()]
+            } else {
+                vec![]
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    fn post(name: &str, tags: &[&str], content: &str) -> MdInfo {
+        MdInfo {
+            title: name.to_owned(),
+            path: PathBuf::from(format!("{name}.md")),
+            tags: tags.iter().map(|x| x.to_string()).collect(),
+            content: content.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    fn related(posts: Vec<MdInfo>, count: usize) -> HashMap<PathBuf, Vec<String>> {
+        let collections = [Collection {
+            name: "posts".to_owned(),
+            dir: PathBuf::new(),
+            layout: None,
+            feed: false,
+            posts,
+        }];
+        related_posts(&collections, count)
+            .into_iter()
+            .map(|(k, v)| (k, v.into_iter().map(|x| x.title).collect()))
+            .collect()
+    }
+
+    #[test]
+    fn shared_tags_outrank_similar_text() {
+        let res = related(
+            vec![
+                post("a", &["rust"], "borrow checker lifetimes"),
+                post("b", &["rust"], "cooking pasta"),
+                post("c", &[], "borrow checker lifetimes explained"),
+                post("d", &[], "gardening tomatoes"),
+            ],
+            3,
+        );
+        assert_eq!(res[Path::new("a.md")], ["b", "c"]);
+        assert_eq!(res[Path::new("c.md")], ["a"]);
+        assert!(res[Path::new("d.md")].is_empty());
+    }
+
+    #[test]
+    fn count_limits_the_list() {
+        let res = related(
+            vec![
+                post("a", &["x"], ""),
+                post("b", &["x"], ""),
+                post("c", &["x"], ""),
+            ],
+            1,
+        );
+        // Equal scores fall back to the posts' order, newest (then last by title) first
+        assert_eq!(res[Path::new("a.md")], ["c"]);
+    }
+
+    #[test]
+    fn terms_are_lowercased_words_and_cjk_characters() {
+        assert_eq!(terms("Rust is FUN, ok"), ["rust", "fun"]);
+        assert_eq!(terms("日本語 text"), ["日", "本", "語", "text"]);
+    }
+
+    #[test]
+    fn scores_are_symmetric_and_reproducible() {
+        let posts = [
+            post("a", &[], "alpha beta gamma delta"),
+            post("b", &[], "gamma delta epsilon zeta"),
+            post("c", &[], "eta theta"),
+        ];
+        let refs = posts.iter().collect::<Vec<_>>();
+        let v = tf_idf(&refs);
+        assert_eq!(cosine(&v[0], &v[1]), cosine(&v[1], &v[0]));
+        assert_eq!(cosine(&v[0], &v[2]), 0.0);
+        assert!((cosine(&v[0], &v[0]) - 1.0).abs() < 1e-12);
+        assert_eq!(tf_idf(&refs), v);
+    }
+}
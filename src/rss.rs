@@ -1,8 +1,20 @@
-pub fn add_rss_meta(contents: &str, url: &str, title: &str) -> String {
-    contents.replace("</head>", &format!(r#"
+use crate::{collection::Collection, config::Config};
+
+pub fn add_rss_meta(contents: &str, collections: &[Collection], c: &Config) -> String {
+    let links = collections
+        .iter()
+        .filter(|x| x.feed)
+        .map(|x| {
+            format!(
+                r#"
       <link rel="alternate"
         type="application/rss+xml"
         href="{}"
-        title="{}">
-    </head>"#, String::from(url) + "/feed.xml", title))
+        title="{}">"#,
+                c.site_url() + "/" + &x.feed_file(),
+                x.feed_title(c)
+            )
+        })
+        .collect::<String>();
+    contents.replace("</head>", &format!("{links}\n    </head>"))
 }
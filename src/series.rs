@@ -0,0 +1,123 @@
+use std::{collections::BTreeMap, fmt::Write, path::Path};
+
+use crate::{
+    collection::Collection,
+    markdown::MdInfo,
+    permalink::{relative_link, slugify},
+};
+
+/// Posts sharing a `series:` front matter value, across every collection
+pub struct Series {
+    pub name: String,
+    /// Location of the generated index page relative to the site root
+    pub url: String,
+    /// By `series_order`, then date; parts without an order come last
+    pub parts: Vec<MdInfo>,
+}
+
+pub fn collect_series(collections: &[Collection]) -> Vec<Series> {
+    let mut by_name: BTreeMap<&str, Vec<MdInfo>> = BTreeMap::new();
+    for post in collections.iter().flat_map(|x| &x.posts) {
+        if let Some(name) = &post.series {
+            by_name.entry(name).or_default().push(post.clone());
+        }
+    }
+    by_name
+        .into_iter()
+        .map(|(name, mut parts)| {
+            parts.sort_by(|a, b| {
+                let order = |x: &MdInfo| (x.series_order.is_none(), x.series_order);
+                order(a).cmp(&order(b)).then_with(|| a.cmp(b))
+            });
+            Series {
+                name: name.to_owned(),
+                url: format!("series/{}/", slugify(name)),
+                parts,
+            }
+        })
+        .collect()
+}
+
+/// The series `post` is part of
+pub fn find<'a>(series: &'a [Series], post: &MdInfo) -> Option<&'a Series> {
+    let name = post.series.as_ref()?;
+    series.iter().find(|x| &x.name == name)
+}
+
+/// `<nav class="series">` for the page at `dest`: the list of parts with the current one marked,
+/// and links to the previous and next part; empty if `post` isn't one of the parts
+pub fn series_nav(series: &Series, post: &MdInfo, dest: &Path, build_dir: &Path) -> String {
+    let link = |x: &MdInfo| relative_link(dest, build_dir, &x.url);
+    let Some(current) = series.parts.iter().position(|x| x.path == post.path) else {
+        return String::new();
+    };
+
+    let mut res = String::from("<nav class=\"series\">\n");
+    write!(
+        res,
+        "<p>Part {} of {} in <a href=\"{}\">{}</a></p>\n<ol>\n",
+        current + 1,
+        series.parts.len(),
+        relative_link(dest, build_dir, &series.url),
+        series.name
+    )
+    .unwrap();
+    for (i, part) in series.parts.iter().enumerate() {
+        if i == current {
+            writeln!(res, "<li aria-current=\"page\">{}</li>", part.title).unwrap();
+        } else {
+            writeln!(
+                res,
+                "<li><a href=\"{}\">{}</a></li>",
+                link(part),
+                part.title
+            )
+            .unwrap();
+        }
+    }
+    res.push_str("</ol>\n");
+
+    let prev = current.checked_sub(1).and_then(|i| series.parts.get(i));
+    let next = series.parts.get(current + 1);
+    if prev.is_some() || next.is_some() {
+        res.push_str("<p>");
+        if let Some(x) = prev {
+            write!(
+                res,
+                "<a rel=\"prev\" href=\"{}\">&larr; {}</a>",
+                link(x),
+                x.title
+            )
+            .unwrap();
+        }
+        if let Some(x) = next {
+            write!(
+                res,
+                " <a rel=\"next\" href=\"{}\">{} &rarr;</a>",
+                link(x),
+                x.title
+            )
+            .unwrap();
+        }
+        res.push_str("</p>\n");
+    }
+    res.push_str("</nav>");
+    res
+}
+
+/// Ordered list of every part, for the series index page at `dest`
+pub fn series_index(series: &Series, dest: &Path, build_dir: &Path) -> String {
+    let mut res = String::from("<ol class=\"series\">\n");
+    for part in &series.parts {
+        writeln!(
+            res,
+            "<li><a href=\"{}\">{}</a> <time>{}</time></li>",
+            relative_link(dest, build_dir, &part.url),
+            part.title,
+            part.date.format("%A %d %B %Y")
+        )
+        .unwrap();
+    }
+    res.push_str("</ol>");
+    res
+}
@@ -0,0 +1,197 @@
+use std::{collections::HashSet, fmt::Write};
+
+use regex::{Captures, Regex};
+
+use crate::{config::TocConfig, permalink::slugify};
+
+/// A heading of a rendered post, as listed in its table of contents
+pub struct Heading {
+    pub level: usize,
+    pub id: String,
+    /// Inner HTML without tags
+    pub text: String,
+}
+
+/// Gives every heading of `html` an `id` (keeping those the markdown backend chose) and a
+/// trailing `<a class="heading-anchor">` linking to itself
+///
+/// Works on the finished HTML, so it doesn't matter which backend produced it. Pandoc's
+/// `<h1 class="title">` page title is left alone.
+pub fn anchor_headings(html: &str) -> (String, Vec<Heading>) {
+    let heading = Regex::new(r"(?s)<h([1-6])\b([^>]*)>(.*?)</h[1-6]>").unwrap();
+    let id_attr = Regex::new(r#"\bid="([^"]*)""#).unwrap();
+    let title_class = Regex::new(r#"\bclass="([^"]*\s)?title(\s[^"]*)?""#).unwrap();
+    let tag = Regex::new(r"<[^>]*>").unwrap();
+
+    let mut used: HashSet<String> = id_attr
+        .captures_iter(html)
+        .map(|x| x[1].to_owned())
+        .collect();
+    let mut headings = vec![];
+
+    let res = heading.replace_all(html, |caps: &Captures| {
+        let (level, attrs, inner) = (&caps[1], &caps[2], &caps[3]);
+        if title_class.is_match(attrs) {
+            return caps[0].to_owned();
+        }
+        let text = tag.replace_all(inner, "").trim().to_owned();
+        let (id, attrs) = match id_attr.captures(attrs) {
+            Some(x) => (x[1].to_owned(), attrs.to_owned()),
+            None => {
+                let id = unique_id(&slugify(&text), &mut used);
+                (id.clone(), format!(r#" id="{id}"{attrs}"#))
+            }
+        };
+        let res = format!(
+            r##"<h{level}{attrs}>{inner} <a class="heading-anchor" href="#{id}" aria-label="Link to this section">#</a></h{level}>"##
+        );
+        headings.push(Heading {
+            level: level.parse().unwrap(),
+            id,
+            text,
+        });
+        res
+    });
+    (res.into_owned(), headings)
+}
+
+/// `slug`, or `slug-1`, `slug-2`... if taken
+fn unique_id(slug: &str, used: &mut HashSet<String>) -> String {
+    let slug = if slug.is_empty() { "section" } else { slug };
+    let mut id = slug.to_owned();
+    let mut n = 0;
+    while used.contains(&id) {
+        n += 1;
+        id = format!("{slug}-{n}");
+    }
+    used.insert(id.clone());
+    id
+}
+
+/// Nested `<nav class="toc">` list of the `depth` levels below the top-most heading, or
+/// nothing if fewer than `min_headings` qualify
+pub fn toc_html(headings: &[Heading], c: &TocConfig) -> String {
+    let Some(top) = headings.iter().map(|x| x.level).min() else {
+        return String::new();
+    };
+    let headings = headings
+        .iter()
+        .filter(|x| x.level < top + c.depth)
+        .collect::<Vec<_>>();
+    if headings.is_empty() || headings.len() < c.min_headings {
+        return String::new();
+    }
+
+    let mut res = String::from(r#"<nav class="toc">"#);
+    // Levels of the lists currently open
+    let mut open: Vec<usize> = vec![];
+    for h in headings {
+        while open.last().is_some_and(|&x| x > h.level) {
+            res.push_str("</li></ul>");
+            open.pop();
+        }
+        if open.last() == Some(&h.level) {
+            res.push_str("</li>");
+        } else {
+            res.push_str("<ul>");
+            open.push(h.level);
+        }
+        write!(res, r##"<li><a href="#{}">{}</a>"##, h.id, h.text).unwrap();
+    }
+    res.push_str(&"</li></ul>".repeat(open.len()));
+    res.push_str("</nav>");
+    res
+}
+
+/// `toc` placed before the first heading it lists
+pub fn insert_toc(html: &str, toc: &str, headings: &[Heading]) -> String {
+    let Some(first) = headings.first() else {
+        return html.to_owned();
+    };
+    let re = Regex::new(&format!(
+        r#"<h[1-6]\b[^>]*\bid="{}""#,
+        regex::escape(&first.id)
+    ))
+    .unwrap();
+    match re.find(html) {
+        Some(m) => format!("{}{toc}\n{}", &html[..m.start()], &html[m.start()..]),
+        None => html.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn toc(depth: usize, min_headings: usize) -> TocConfig {
+        TocConfig {
+            depth,
+            min_headings,
+        }
+    }
+
+    #[test]
+    fn headings_get_unique_ids_and_anchors() {
+        let (html, headings) = anchor_headings("<h2>Intro</h2><h2>Intro</h2><h3 id=\"x\">Set</h3>");
+        let ids = headings.iter().map(|x| x.id.as_str()).collect::<Vec<_>>();
+        assert_eq!(ids, ["intro", "intro-1", "x"]);
+        assert!(
+            html.starts_with(r##"<h2 id="intro">Intro <a class="heading-anchor" href="#intro""##)
+        );
+    }
+
+    #[test]
+    fn generated_ids_avoid_existing_ones() {
+        let (_, headings) = anchor_headings(r#"<p id="intro"></p><h2>Intro</h2><h2>!!</h2>"#);
+        let ids = headings.iter().map(|x| x.id.as_str()).collect::<Vec<_>>();
+        assert_eq!(ids, ["intro-1", "section"]);
+    }
+
+    #[test]
+    fn title_and_markup_are_handled() {
+        let (html, headings) =
+            anchor_headings(r#"<h1 class="title">T</h1><h2>A <code>b</code></h2>"#);
+        assert!(html.starts_with(r#"<h1 class="title">T</h1>"#));
+        assert_eq!(headings.len(), 1);
+        assert_eq!(headings[0].text, "A b");
+        assert_eq!(headings[0].id, "a-b");
+    }
+
+    fn heading(level: usize, id: &str) -> Heading {
+        Heading {
+            level,
+            id: id.to_owned(),
+            text: id.to_owned(),
+        }
+    }
+
+    #[test]
+    fn toc_nests_by_level() {
+        let headings = [heading(2, "a"), heading(3, "b"), heading(2, "c")];
+        assert_eq!(
+            toc_html(&headings, &toc(3, 2)),
+            r##"<nav class="toc"><ul><li><a href="#a">a</a><ul><li><a href="#b">b</a></li></ul></li><li><a href="#c">c</a></li></ul></nav>"##
+        );
+    }
+
+    #[test]
+    fn toc_respects_depth_and_minimum() {
+        let headings = [heading(2, "a"), heading(4, "b")];
+        assert_eq!(
+            toc_html(&headings, &toc(2, 1)),
+            r##"<nav class="toc"><ul><li><a href="#a">a</a></li></ul></nav>"##
+        );
+        assert_eq!(toc_html(&headings, &toc(2, 2)), "");
+        assert_eq!(toc_html(&[], &toc(3, 0)), "");
+    }
+
+    #[test]
+    fn toc_goes_before_the_first_heading() {
+        let headings = [heading(2, "a")];
+        assert_eq!(
+            insert_toc("<p>x</p><h2 id=\"a\">a</h2>", "TOC", &headings),
+            "<p>x</p>TOC\n<h2 id=\"a\">a</h2>"
+        );
+        assert_eq!(insert_toc("<p>x</p>", "TOC", &[]), "<p>x</p>");
+    }
+}